@@ -1,5 +1,10 @@
 function change_password
     set current $(pwd)
+    if not command -q socat
+        echo "❌ socat is needed to use the control socket, install it with: sudo apt install socat"
+        return 1
+    end
+
     if test (count $argv) -ne 1
         echo "Usage: change_password [name]"
         return 1
@@ -24,13 +29,14 @@ function change_password
     # 5) restart the ical container
     set name (basename "$folder")
 
-    if not test -f $folder/kuma/pipe
-        echo "❌ Missing pipe"
+    if not test -S $folder/kuma/control.sock
+        echo "❌ Missing control socket"
         return 1
     end
 
     # docker start webcom_$name-webcom_ical-1
-    echo "e" > $folder/kuma/pipe
+    echo '{"command": "reload-config"}' | socat - UNIX-CONNECT:$folder/kuma/control.sock
+    echo '{"command": "force-run"}' | socat - UNIX-CONNECT:$folder/kuma/control.sock
 
 
     # 6) wait for logbook.json to change
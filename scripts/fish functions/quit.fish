@@ -1,6 +1,11 @@
 function kumaquit
     set -l all_mode 0
 
+    if not command -q socat
+        echo "❌ socat is needed to use the control socket, install it with: sudo apt install socat"
+        return 1
+    end
+
     # check if -a was given
     if test (count $argv) -gt 0
         if test $argv[1] = "-a"
@@ -16,15 +21,15 @@ function kumaquit
             if string match -q "_*" $dir
                 continue
             end
-            if test -p "$dir/kuma/pipe"
-                echo "q" > "$dir/kuma/pipe"
+            if test -S "$dir/kuma/control.sock"
+                echo '{"command": "shutdown"}' | socat - UNIX-CONNECT:"$dir/kuma/control.sock"
             end
         end
     else
-        if test -p "./kuma/pipe"
-            echo "q" > "./kuma/pipe"
+        if test -S "./kuma/control.sock"
+            echo '{"command": "shutdown"}' | socat - UNIX-CONNECT:./kuma/control.sock
         else
-            echo "No pipe found in ./kuma/pipe"
+            echo "No control socket found in ./kuma/control.sock"
         end
     end
 end
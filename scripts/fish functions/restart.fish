@@ -1,12 +1,17 @@
 function restart
     set name (basename "$folder")
 
-    if not test -f ./kuma/pipe
-        echo "❌ Missing pipe"
+    if not command -q socat
+        echo "❌ socat is needed to use the control socket, install it with: sudo apt install socat"
         return 1
     end
 
-    echo "e" > ./kuma/pipe
+    if not test -S ./kuma/control.sock
+        echo "❌ Missing control socket"
+        return 1
+    end
+
+    echo '{"command": "run"}' | socat - UNIX-CONNECT:./kuma/control.sock
     
     while true
         sleep 3
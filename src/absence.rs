@@ -0,0 +1,181 @@
+use std::fs::{read_to_string, write};
+
+use dotenvy::var;
+use serde::{Deserialize, Serialize};
+use time::{Date, Month};
+
+use crate::{GenResult, create_path};
+
+const ABSENCES_FILENAME: &str = "absences.json";
+
+// What a day without a shift in the roster means
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DayStatus {
+    DayOff,
+    Leave,
+    Sick,
+    // Anything else webcom shows on a day, with the label webcom uses
+    Other(String),
+}
+
+impl DayStatus {
+    /*
+    Reads the status from the tooltip of a day in the roster which is not a shift
+    Only the first line is used, as the other lines can contain the day of the week, like Vrijdag
+    Returns None if the tooltip is empty
+    */
+    pub fn from_tooltip(tooltip: &str) -> Option<Self> {
+        let label = tooltip_parts(tooltip).into_iter().next()?;
+        let text = label.to_lowercase();
+        Some(if text.contains("ziek") {
+            Self::Sick
+        } else if text.contains("verlof") || text.contains("vakantie") {
+            Self::Leave
+        } else if text.contains("vrij") || text.contains("rust") || text.contains("compensatie") {
+            Self::DayOff
+        } else {
+            Self::Other(label)
+        })
+    }
+
+    // The name shown in the calendar
+    pub fn summary(&self) -> &str {
+        match self {
+            Self::DayOff => "Vrij",
+            Self::Leave => "Verlof",
+            Self::Sick => "Ziek",
+            Self::Other(label) => label,
+        }
+    }
+}
+
+// A day in the roster which is not a shift, like a day off or sick leave
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Absence {
+    pub date: Date,
+    pub status: DayStatus,
+    // Every line of the tooltip
+    pub description: String,
+}
+
+impl Absence {
+    pub fn new(tooltip: &str, date: Date) -> Option<Self> {
+        Some(Self {
+            date,
+            status: DayStatus::from_tooltip(tooltip)?,
+            description: tooltip_parts(tooltip).join("\n"),
+        })
+    }
+}
+
+// The lines of a tooltip, webcom separates them with bullets
+fn tooltip_parts(tooltip: &str) -> Vec<String> {
+    tooltip
+        .split('•')
+        .map(|part| part.replace('\u{a0}', " ").trim().to_owned())
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+// Where absences are put, set with ABSENCE_EVENTS
+#[derive(Debug, Clone, PartialEq)]
+pub enum AbsenceCalendar {
+    // In the same calendar as the shifts
+    Main,
+    // In their own calendar file next to the main calendar
+    Separate,
+    None,
+}
+
+impl AbsenceCalendar {
+    pub fn from_env() -> Self {
+        match var("ABSENCE_EVENTS").unwrap_or_default().as_str() {
+            "separate" => Self::Separate,
+            "false" | "none" => Self::None,
+            _ => Self::Main,
+        }
+    }
+}
+
+/*
+All absences found in the roster, stored like the shifts so months which are not loaded again keep their absences
+Loading a month replaces all stored absences of that month
+*/
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AbsenceStore {
+    absences: Vec<Absence>,
+}
+
+impl AbsenceStore {
+    pub fn load() -> Self {
+        match read_to_string(create_path(ABSENCES_FILENAME)) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|error| {
+                warn!("Stored absences could not be read, starting over. Error: {error}");
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self) -> GenResult<()> {
+        write(
+            create_path(ABSENCES_FILENAME),
+            serde_json::to_string_pretty(self)?.as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    pub fn replace_month(&mut self, year: i32, month: Month, absences: &[Absence]) {
+        self.absences
+            .retain(|absence| (absence.date.year(), absence.date.month()) != (year, month));
+        self.absences.extend_from_slice(absences);
+        self.absences.sort_by_key(|absence| absence.date);
+    }
+
+    pub fn absences(&self) -> &[Absence] {
+        &self.absences
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(month: Month, day: u8) -> Date {
+        Date::from_calendar_date(2025, month, day).unwrap()
+    }
+
+    #[test]
+    fn day_status_is_read_from_tooltip() {
+        let status = |tooltip: &str| DayStatus::from_tooltip(tooltip);
+        assert_eq!(
+            status("Vrij\u{a0}• \u{a0}• Dagsoort: Zaterdag"),
+            Some(DayStatus::DayOff)
+        );
+        assert_eq!(
+            status("Cursus\u{a0}• \u{a0}• Dagsoort: Vrijdag"),
+            Some(DayStatus::Other("Cursus".to_owned()))
+        );
+        assert_eq!(status("Roostervrij"), Some(DayStatus::DayOff));
+        assert_eq!(status("Verlof: Vakantieverlof"), Some(DayStatus::Leave));
+        assert_eq!(status("Ziekmelding"), Some(DayStatus::Sick));
+        assert_eq!(
+            status("Cursus\u{a0}• \u{a0}• Rijvaardigheid"),
+            Some(DayStatus::Other("Cursus".to_owned()))
+        );
+        assert_eq!(status(""), None);
+        assert_eq!(status("\u{a0}• "), None);
+    }
+
+    #[test]
+    fn loading_a_month_replaces_its_absences() {
+        let mut store = AbsenceStore::default();
+        let june = Absence::new("Vrij", date(Month::June, 2)).unwrap();
+        let july = Absence::new("Verlof", date(Month::July, 1)).unwrap();
+        store.replace_month(2025, Month::July, std::slice::from_ref(&july));
+        store.replace_month(2025, Month::June, std::slice::from_ref(&june));
+        assert_eq!(store.absences(), [june.clone(), july.clone()]);
+        store.replace_month(2025, Month::June, &[]);
+        assert_eq!(store.absences(), [july]);
+    }
+}
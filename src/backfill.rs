@@ -0,0 +1,229 @@
+use std::fs::{read_to_string, remove_file, write};
+
+use dotenvy::var;
+use serde::{Deserialize, Serialize};
+use time::{Date, Month};
+
+use crate::{
+    GenResult,
+    absence::AbsenceStore,
+    create_path,
+    errors::ResultLog,
+    feeds::ShiftChanges,
+    gebroken_shifts::load_broken_shift_information,
+    health::ApplicationLogbook,
+    ical::{get_ical_path, load_stored_shifts, merge_non_relevant_shifts},
+    page_cache::PageCache,
+    parsing::{load_calendar, load_month_shifts, offset_month},
+    status::{active_run, try_lock_run},
+    webcom_client::{WebcomBackend, WebcomClient},
+    write_calendar,
+};
+
+const PROGRESS_FILENAME: &str = "backfill_progress.json";
+
+// Keeps track of which months have been backfilled, so an interrupted backfill can resume
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct BackfillProgress {
+    since: Date,
+    completed: Option<Date>,
+}
+
+impl BackfillProgress {
+    // Load the previous progress, only if it was for the same starting month
+    fn load(since: Date) -> Self {
+        let path = create_path(PROGRESS_FILENAME);
+        match || -> GenResult<Self> { Ok(serde_json::from_str(&read_to_string(path)?)?) }() {
+            Ok(progress) if progress.since == since => {
+                if let Some(completed) = progress.completed {
+                    info!("Resuming backfill after {completed}");
+                }
+                progress
+            }
+            _ => Self {
+                since,
+                completed: None,
+            },
+        }
+    }
+
+    // The first month which still needs to be loaded
+    fn start(&self) -> GenResult<Date> {
+        Ok(match self.completed {
+            Some(completed) => {
+                let (year, month) = offset_month(completed, 1);
+                Date::from_calendar_date(year, month, 1)?
+            }
+            None => self.since,
+        })
+    }
+
+    fn save(&self) -> GenResult<()> {
+        write(
+            create_path(PROGRESS_FILENAME),
+            serde_json::to_string(self)?.as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn remove(&self) -> GenResult<()> {
+        Ok(remove_file(create_path(PROGRESS_FILENAME))?)
+    }
+}
+
+/*
+Returns all months from since up to the month where shifts start being relevant
+Those relevant months are already loaded by every normal run
+*/
+fn months_to_backfill(since: Date, today: Date) -> Vec<(i32, Month)> {
+    let month_index = |(year, month): (i32, Month)| year * 12 + month as i32;
+    let first_relevant_month = month_index(offset_month(today, -1));
+    (0..)
+        .map(|offset| offset_month(since, offset))
+        .take_while(|month| month_index(*month) < first_relevant_month)
+        .collect()
+}
+
+/*
+Loads every month since the given month and adds the shifts to the stored non-relevant shifts
+No mails are sent, as all these shifts are in the past anyway
+Progress is saved after every month, so it continues where it left off if it is interrupted
+*/
+pub async fn backfill(since: Date) -> GenResult<()> {
+    if !get_ical_path()?.exists() {
+        return Err(
+            "No calendar found, let webcom ical run at least once before backfilling".into(),
+        );
+    }
+    // A run writes the stored shifts and the calendar too, so the lock is held until the backfill is done
+    let Some(_run_lock) = try_lock_run()? else {
+        return Err(match active_run() {
+            Some(run) => format!(
+                "A run started by {} {} seconds ago is in progress, backfill again when it is done",
+                run.start_reason, run.elapsed_seconds
+            ),
+            None => "Another backfill is in progress".to_owned(),
+        }
+        .into());
+    };
+    let username = var("USERNAME")?;
+    let password = var("PASSWORD")?;
+    let mut progress = BackfillProgress::load(since);
+    let start = progress.start()?;
+    let today = time::OffsetDateTime::now_utc().date();
+    let months = months_to_backfill(start, today);
+    if months.is_empty() && progress.completed.is_none() {
+        info!("Nothing to backfill");
+        return Ok(());
+    }
+    info!("Backfilling {} months", months.len());
+    if !months.is_empty() {
+        load_months(&months, &mut progress, &username, &password).await?;
+    }
+
+    // The calendar is rebuilt, otherwise the backfilled shifts are lost when it is used to restore the stored shifts
+    let all_shifts = load_stored_shifts()?;
+    write_calendar(
+        &all_shifts,
+        AbsenceStore::load().absences(),
+        ShiftChanges::load().changes(),
+        &ApplicationLogbook::load().state,
+    )?;
+    progress.remove().warn("Removing backfill progress");
+    info!("Backfill done");
+    Ok(())
+}
+
+// Signs in and loads the months one by one, saving the progress after every month
+async fn load_months(
+    months: &[(i32, Month)],
+    progress: &mut BackfillProgress,
+    username: &str,
+    password: &str,
+) -> GenResult<()> {
+    let mut client = WebcomBackend::connect().await?;
+    let mut logbook = ApplicationLogbook::default();
+    let mut page_cache = PageCache::load();
+    let mut absences = AbsenceStore::load();
+    let result = async {
+        client.open(0).await?;
+        load_calendar(&mut client, username, password).await?;
+        for (year, month) in months.iter().copied() {
+            let shifts = load_month_shifts(
+                &mut client,
+                &mut logbook,
+                &mut page_cache,
+                &mut absences,
+                year,
+                month,
+            )
+            .await?;
+            let shifts =
+                load_broken_shift_information(&mut client, &shifts, &mut page_cache).await?;
+            merge_non_relevant_shifts(&shifts)?;
+            absences.save().warn("Saving absences");
+            progress.completed = Some(Date::from_calendar_date(year, month, 1)?);
+            progress.save().warn("Saving backfill progress");
+            info!(
+                "Backfilled {} shifts of {year}-{}",
+                shifts.len(),
+                month as u8
+            );
+        }
+        Ok::<(), crate::GenError>(())
+    }
+    .await;
+    client.quit().await.warn("Stopping client");
+    page_cache.save().warn("Saving page cache");
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: Month, day: u8) -> Date {
+        Date::from_calendar_date(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn months_roll_over_years() {
+        let today = date(2025, Month::March, 10);
+        assert_eq!(
+            months_to_backfill(date(2024, Month::November, 1), today),
+            [
+                (2024, Month::November),
+                (2024, Month::December),
+                (2025, Month::January),
+            ]
+        );
+    }
+
+    #[test]
+    fn relevant_months_are_not_backfilled() {
+        let today = date(2025, Month::June, 10);
+        assert!(months_to_backfill(date(2025, Month::May, 1), today).is_empty());
+        assert!(months_to_backfill(date(2025, Month::June, 1), today).is_empty());
+        assert_eq!(
+            months_to_backfill(date(2025, Month::April, 1), today),
+            [(2025, Month::April)]
+        );
+    }
+
+    #[test]
+    fn backfill_resumes_after_the_completed_month() {
+        let since = date(2024, Month::October, 1);
+        let mut progress = BackfillProgress {
+            since,
+            completed: None,
+        };
+        assert_eq!(progress.start().unwrap(), since);
+        progress.completed = Some(date(2024, Month::December, 1));
+        let start = progress.start().unwrap();
+        assert_eq!(start, date(2025, Month::January, 1));
+        assert_eq!(
+            months_to_backfill(start, date(2025, Month::March, 10)),
+            [(2025, Month::January)]
+        );
+    }
+}
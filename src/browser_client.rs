@@ -0,0 +1,294 @@
+use dotenvy::var;
+use thirtyfour::{error::WebDriverResult, prelude::*};
+use time::{Date, Month};
+
+use crate::{
+    FALLBACK_URL, FailureType, GenResult, MAIN_URL,
+    email::DATE_DESCRIPTION,
+    errors::{WebcomError, check_if_webcom_unavailable, check_sign_in_result},
+    messages::{
+        ACKNOWLEDGE_BUTTON_ID, MESSAGE_TABLE_ID, MESSAGES_PATH, WebcomMessage, parse_message_rows,
+    },
+    parsing::parse_user_name,
+    webcom_client::{CalendarDay, ShiftRows, WebcomClient, custom_webcom_url},
+};
+
+// Collects the tooltip and day text of every day in the roster in a single request
+const CAL_DAY_SCRIPT: &str =
+    "return Array.from(document.getElementsByClassName('calDay')).map(function(day) {
+    var strong = day.getElementsByTagName('strong')[0];
+    return [day.getAttribute('data-original-title'), strong ? strong.innerText : ''];
+});";
+// Collects the text of every column in the trip table
+const SHIFT_ROWS_SCRIPT: &str = "var body = document.getElementsByTagName('tbody')[0];
+if (!body) { return null; }
+return Array.from(body.getElementsByTagName('tr')).map(function(row) {
+    return Array.from(row.getElementsByTagName('td')).map(function(column) { return column.innerText; });
+});";
+// Same as the trip table, but for the table of the messages page. The id is given as argument
+const MESSAGE_ROWS_SCRIPT: &str = "var table = document.getElementById(arguments[0]);
+if (!table) { return null; }
+return Array.from(table.getElementsByTagName('tr')).map(function(row) {
+    return Array.from(row.getElementsByTagName('td')).map(function(column) { return column.innerText; });
+});";
+
+// Drives a headless Firefox through geckodriver
+pub struct BrowserClient {
+    driver: WebDriver,
+}
+
+impl BrowserClient {
+    pub async fn new() -> GenResult<Self> {
+        let gecko_ip = var("GECKO_IP")?;
+        let caps = DesiredCapabilities::firefox();
+        let driver = WebDriver::new(format!("http://{}", gecko_ip), caps).await?;
+        Ok(Self { driver })
+    }
+
+    async fn h3_text(&self) -> Option<String> {
+        match self.driver.find(By::Tag("h3")).await {
+            Ok(element) => Some(element.text().await.unwrap_or("GEEN TEKST".to_owned())),
+            Err(_) => None,
+        }
+    }
+
+    async fn on_messages_page(&self) -> bool {
+        matches!(self.driver.current_url().await, Ok(url) if url.path() == MESSAGES_PATH)
+    }
+}
+
+impl WebcomClient for BrowserClient {
+    async fn open(&mut self, retry_count: usize) -> GenResult<()> {
+        let driver = &self.driver;
+        driver.delete_all_cookies().await?;
+        let custom_url = custom_webcom_url();
+        let main_url = custom_url.clone().unwrap_or(MAIN_URL.to_owned());
+        info!("Loading site: {}..", main_url);
+        match driver.goto(&main_url).await {
+            Ok(_) => wait_untill_redirect(driver).await?,
+            Err(_) if custom_url.is_some() => return Err(FailureType::ConnectError.into()),
+            Err(_) => {
+                error!(
+                    "Failed waiting for redirect. Going to fallback {}",
+                    FALLBACK_URL[retry_count % FALLBACK_URL.len()]
+                );
+                driver
+                    .goto(FALLBACK_URL[retry_count % FALLBACK_URL.len()])
+                    .await
+                    .map_err(|_| FailureType::ConnectError)?
+            }
+        };
+        Ok(())
+    }
+
+    async fn login(&mut self, user: &str, pass: &str) -> GenResult<()> {
+        let driver = &self.driver;
+        let possible_error = self.h3_text().await;
+        let username_field = driver
+            .find(By::Id("ctl00_cntMainBody_lgnView_lgnLogin_UserName"))
+            .await
+            .map_err(|error| match check_if_webcom_unavailable(possible_error) {
+                true => FailureType::SignInFailed(crate::SignInFailure::WebcomDown),
+                false => FailureType::Other(error.to_string()),
+            })?;
+        username_field.send_keys(user).await?;
+        let password_field = driver
+            .find(By::Id("ctl00_cntMainBody_lgnView_lgnLogin_Password"))
+            .await?;
+        password_field.send_keys(pass).await?;
+        driver
+            .find(By::Id("ctl00_cntMainBody_lgnView_lgnLogin_LoginButton"))
+            .await?
+            .click()
+            .await?;
+        debug!("waiting until login page is loaded");
+        let _ = wait_for_response(driver, By::Tag("h3"), false).await;
+        debug!("login page is loaded");
+        let banner_text = match driver.find(By::Id("ctl00_lblMessage")).await {
+            Ok(element) => Some(element.text().await?),
+            Err(_) => None,
+        };
+        check_sign_in_result(
+            self.on_messages_page().await,
+            self.h3_text().await,
+            banner_text,
+        )
+    }
+
+    async fn current_user_name(&mut self) -> GenResult<Option<String>> {
+        if self.on_messages_page().await {
+            info!("Got redirected to message, wont try to get name");
+            return Ok(None);
+        }
+        let name_text = self.driver.find(By::Tag("h3")).await?.text().await?;
+        Ok(Some(parse_user_name(&name_text)?))
+    }
+
+    async fn pending_messages(&mut self) -> GenResult<Vec<WebcomMessage>> {
+        if !self.on_messages_page().await {
+            return Ok(vec![]);
+        }
+        let rows: Option<Vec<Vec<String>>> = self
+            .driver
+            .execute(MESSAGE_ROWS_SCRIPT, vec![MESSAGE_TABLE_ID.into()])
+            .await?
+            .convert()?;
+        match rows {
+            Some(rows) => Ok(parse_message_rows(rows)),
+            None => Err(WebcomError::scraping("Geen berichten tabel gevonden")),
+        }
+    }
+
+    async fn acknowledge_messages(&mut self) -> GenResult<()> {
+        if !self.on_messages_page().await {
+            return Ok(());
+        }
+        let driver = &self.driver;
+        match driver.find(By::Id(ACKNOWLEDGE_BUTTON_ID)).await {
+            Ok(button) => {
+                info!("Marking webcom messages as read");
+                button.click().await?;
+            }
+            Err(_) => {
+                warn!("No button to mark the webcom messages as read, opening the home page");
+                navigate_to_subdirectory(driver, "/WebComm/home.aspx").await?
+            }
+        }
+        wait_until_loaded(driver).await?;
+        if self.on_messages_page().await {
+            return Err(WebcomError::scraping(
+                "Berichten pagina kon niet verlaten worden",
+            ));
+        }
+        Ok(())
+    }
+
+    async fn fetch_month(&mut self, year: i32, month: Month) -> GenResult<Vec<CalendarDay>> {
+        navigate_to_subdirectory(
+            &self.driver,
+            &format!("roster.aspx?{}-{}-01", year, month as u8),
+        )
+        .await?;
+        wait_until_loaded(&self.driver).await?;
+        let days: Vec<(Option<String>, String)> = self
+            .driver
+            .execute(CAL_DAY_SCRIPT, vec![])
+            .await?
+            .convert()?;
+        Ok(days
+            .into_iter()
+            .map(|(tooltip, day_text)| CalendarDay { tooltip, day_text })
+            .collect())
+    }
+
+    async fn fetch_shift_detail(&mut self, date: Date) -> GenResult<ShiftRows> {
+        let driver = &self.driver;
+        let formatted_date = date.format(DATE_DESCRIPTION)?;
+        navigate_to_subdirectory(driver, &format!("/WebComm/shift.aspx?{}", formatted_date))
+            .await?;
+        wait_for_response(driver, By::PartialLinkText("Werk en afwezigheden"), true).await?;
+        let rows: Option<ShiftRows> = driver.execute(SHIFT_ROWS_SCRIPT, vec![]).await?.convert()?;
+        navigate_to_subdirectory(driver, "/WebComm/roster.aspx").await?; //Ga terug naar de rooster pagina, anders laden de gebroken shifts niet goed
+        wait_for_response(driver, By::ClassName("calDay"), false).await?;
+        rows.ok_or(WebcomError::scraping("Geen ritten tabel gevonden"))
+    }
+
+    async fn quit(self) -> GenResult<()> {
+        Ok(self.driver.quit().await?)
+    }
+}
+
+async fn wait_until_loaded(driver: &WebDriver) -> GenResult<()> {
+    let mut started_loading = false;
+    let timeout_duration = std::time::Duration::from_secs(30);
+    let _ = tokio::time::timeout(timeout_duration, async {
+        loop {
+            let ready_state: ScriptRet =
+                driver.execute("return document.readyState", vec![]).await?;
+            let current_state = format!("{:?}", ready_state.json());
+            if current_state == "String(\"complete\")" && started_loading {
+                tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+                return Ok::<(), WebDriverError>(());
+            }
+            if current_state == "String(\"loading\")" {
+                started_loading = true;
+            }
+            tokio::task::yield_now().await;
+        }
+    })
+    .await?;
+    Ok(())
+}
+
+async fn wait_untill_redirect(driver: &WebDriver) -> GenResult<()> {
+    let initial_url = driver.current_url().await?;
+    let mut current_url = driver.current_url().await?;
+    let timeout = std::time::Duration::from_secs(30); // Maximum wait time.
+
+    tokio::time::timeout(timeout, async {
+        loop {
+            let new_url = driver.current_url().await.unwrap();
+            if new_url != current_url {
+                current_url = new_url;
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+    })
+    .await?;
+
+    if current_url == initial_url {
+        warn!("Timeout waiting for redirect.");
+        return Err(WebDriverError::Timeout("Redirect did not occur".into()).into());
+    }
+
+    debug!("Redirected to: {}", current_url);
+    wait_until_loaded(driver).await?;
+    Ok(())
+}
+
+/*
+A function to navigate to a subdirectory of the current URL
+Needed because if the while url is entered, the cookies will be lost and you will have to log in again
+*/
+async fn navigate_to_subdirectory(driver: &WebDriver, subdirectory: &str) -> WebDriverResult<()> {
+    let script = format!("window.location.href = '{}';", subdirectory);
+    driver.execute(&script, vec![]).await?;
+    Ok(())
+}
+
+/*
+A simple function to wait until a page is truly fully loaded
+You need to provide a element on the page to wait for
+If clickable is false it will only check if it is displayed, not clickable
+*/
+async fn wait_for_response(
+    driver: &WebDriver,
+    element: By,
+    clickable: bool,
+) -> WebDriverResult<()> {
+    let query = driver.query(element.clone()).first().await?;
+    match clickable {
+        true => {
+            query
+                .wait_until()
+                .wait(
+                    std::time::Duration::from_secs(60),
+                    std::time::Duration::from_secs(1),
+                )
+                .clickable()
+                .await?
+        }
+        false => {
+            query
+                .wait_until()
+                .wait(
+                    std::time::Duration::from_secs(60),
+                    std::time::Duration::from_secs(1),
+                )
+                .displayed()
+                .await?
+        }
+    };
+    Ok(())
+}
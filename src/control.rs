@@ -0,0 +1,283 @@
+use std::{
+    fs::{self, DirBuilder, Permissions},
+    os::unix::fs::{DirBuilderExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use dotenvy::{dotenv_override, var};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::mpsc::{self, Sender},
+};
+
+use crate::{
+    GenResult, create_path, email::send_welcome_mail, errors::ResultLog, execution::StartReason,
+    ical::get_ical_path, kuma, status::RunStatus,
+};
+
+const SOCKET_FILENAME: &str = "control.sock";
+// Only the user running webcom ical can enter this directory, the socket is created here before it is moved next to it
+const PRIVATE_DIRECTORY: &str = "control";
+
+/*
+A command sent to the control socket, one JSON object per line
+e.g. {"command": "force-run"}
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum ControlRequest {
+    /// Load the roster now
+    Run,
+    /// Load the roster now, even if signing in failed before
+    ForceRun,
+    /// Show the state of the last run
+    Status,
+    /// Send the welcome mail again
+    SendWelcome,
+    /// Create the kuma monitor if it does not exist
+    KumaSync,
+    /// Reload the .env file
+    ReloadConfig,
+    /// Stop webcom ical
+    Shutdown,
+}
+
+// The answer to every request, also one JSON object per line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ControlResponse {
+    pub ok: bool,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<RunStatus>,
+}
+
+impl ControlResponse {
+    fn ok(message: &str) -> Self {
+        Self {
+            ok: true,
+            message: message.to_owned(),
+            status: None,
+        }
+    }
+
+    fn error(message: &str) -> Self {
+        Self {
+            ok: false,
+            message: message.to_owned(),
+            status: None,
+        }
+    }
+}
+
+pub fn socket_path() -> PathBuf {
+    create_path(SOCKET_FILENAME)
+}
+
+/*
+Listens on the control socket until a shutdown request is received
+Only the user running webcom ical can use the socket
+*/
+pub async fn start_control_socket(tx: Sender<StartReason>) -> GenResult<()> {
+    let path = socket_path();
+    if path.exists() {
+        info!("Previous control socket found, removing");
+        fs::remove_file(&path).warn("Removing previous control socket");
+    }
+    let listener = bind_private(&path, &create_path(PRIVATE_DIRECTORY))?;
+    serve(listener, tx).await;
+    fs::remove_file(&path).warn("Removing control socket");
+    Ok(())
+}
+
+/*
+Binds the socket in a directory only this user can enter, sets its permissions and only then moves it to path
+So there is no moment where other users can connect to it
+*/
+fn bind_private(path: &Path, private_directory: &Path) -> GenResult<UnixListener> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(private_directory)?;
+    fs::set_permissions(private_directory, Permissions::from_mode(0o700))?;
+    let private_path = private_directory.join(SOCKET_FILENAME);
+    if private_path.exists() {
+        fs::remove_file(&private_path)?;
+    }
+    let listener = UnixListener::bind(&private_path)?;
+    fs::set_permissions(&private_path, Permissions::from_mode(0o600))?;
+    fs::rename(&private_path, path)?;
+    Ok(listener)
+}
+
+async fn serve(listener: UnixListener, tx: Sender<StartReason>) {
+    let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
+    loop {
+        tokio::select! {
+            connection = listener.accept() => match connection {
+                Ok((stream, _)) => {
+                    let tx = tx.clone();
+                    let shutdown_tx = shutdown_tx.clone();
+                    tokio::spawn(async move {
+                        handle_connection(stream, tx, shutdown_tx)
+                            .await
+                            .warn("Control connection");
+                    });
+                }
+                Err(error) => warn!("Accepting control connection failed: {error}"),
+            },
+            _ = shutdown_rx.recv() => return,
+        }
+    }
+}
+
+async fn handle_connection(
+    stream: UnixStream,
+    tx: Sender<StartReason>,
+    shutdown_tx: Sender<()>,
+) -> GenResult<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = serde_json::from_str::<ControlRequest>(&line).ok();
+        debug!("Received control request: {request:?}");
+        let response = match request {
+            Some(request) => handle_request(request, &tx).await,
+            None => ControlResponse::error(&format!("Invalid request: {line}")),
+        };
+        writer
+            .write_all(format!("{}\n", serde_json::to_string(&response)?).as_bytes())
+            .await?;
+        if request == Some(ControlRequest::Shutdown) {
+            _ = shutdown_tx.send(()).await;
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn handle_request(request: ControlRequest, tx: &Sender<StartReason>) -> ControlResponse {
+    let start_run = |start_reason: StartReason| match tx.try_send(start_reason) {
+        Ok(()) => ControlResponse::ok("Run started"),
+        Err(_) => ControlResponse::error("A run is already waiting to start"),
+    };
+    match request {
+        ControlRequest::Run => start_run(StartReason::Control),
+        ControlRequest::ForceRun => start_run(StartReason::Force),
+        ControlRequest::Status => ControlResponse {
+            status: Some(RunStatus::load()),
+            ..ControlResponse::ok("Status loaded")
+        },
+        ControlRequest::SendWelcome => {
+            match get_ical_path().and_then(|ical_path| send_welcome_mail(&ical_path, true)) {
+                Ok(()) => ControlResponse::ok("Welcome mail sent"),
+                Err(error) => {
+                    ControlResponse::error(&format!("Sending welcome mail failed: {error}"))
+                }
+            }
+        }
+        ControlRequest::KumaSync => {
+            let kuma_url = var("KUMA_URL").unwrap_or_default();
+            if kuma_url.is_empty() {
+                return ControlResponse::error("KUMA_URL is not set");
+            }
+            let username = var("USERNAME").unwrap_or_default();
+            match kuma::first_run(&kuma_url, &username).await {
+                Ok(_) => ControlResponse::ok("Kuma is up to date"),
+                Err(error) => ControlResponse::error(&format!("Kuma sync failed: {error}")),
+            }
+        }
+        ControlRequest::ReloadConfig => match dotenv_override() {
+            Ok(_) => ControlResponse::ok("Config reloaded"),
+            Err(error) => ControlResponse::error(&format!("Reloading config failed: {error}")),
+        },
+        ControlRequest::Shutdown => ControlResponse::ok("Stopping webcom ical"),
+    }
+}
+
+// Sends a single request to the control socket of a running webcom ical
+pub async fn send_request(path: &Path, request: ControlRequest) -> GenResult<ControlResponse> {
+    let stream = UnixStream::connect(path).await?;
+    let (reader, mut writer) = stream.into_split();
+    writer
+        .write_all(format!("{}\n", serde_json::to_string(&request)?).as_bytes())
+        .await?;
+    let line = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or("Control socket closed without a response")?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+// The ctl command, prints the response and fails if the request failed
+pub async fn run_ctl(request: ControlRequest, socket: Option<PathBuf>) -> GenResult<()> {
+    let response = send_request(&socket.unwrap_or(socket_path()), request).await?;
+    match &response.status {
+        Some(status) => println!("{}", serde_json::to_string_pretty(status)?),
+        None => println!("{}", response.message),
+    }
+    match response.ok {
+        true => Ok(()),
+        false => Err(response.message.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn requests_get_a_response() {
+        let path = std::env::temp_dir().join(format!("webcom_control_{}.sock", std::process::id()));
+        _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let (tx, mut rx) = mpsc::channel(1);
+        let server = tokio::spawn(serve(listener, tx));
+
+        let response = send_request(&path, ControlRequest::ForceRun).await.unwrap();
+        assert!(response.ok);
+        assert!(rx.recv().await.unwrap() == StartReason::Force);
+
+        send_request(&path, ControlRequest::Run).await.unwrap();
+        let response = send_request(&path, ControlRequest::Run).await.unwrap();
+        assert!(!response.ok, "The first run has not started yet");
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"{\"command\": \"dance\"}\n")
+            .await
+            .unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        let response: ControlResponse =
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert!(!response.ok);
+        assert!(response.message.starts_with("Invalid request"));
+
+        let response = send_request(&path, ControlRequest::Shutdown).await.unwrap();
+        assert!(response.ok);
+        server.await.unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn socket_is_only_usable_by_its_user() {
+        let directory =
+            std::env::temp_dir().join(format!("webcom_control_dir_{}", std::process::id()));
+        let path = directory.join(SOCKET_FILENAME);
+        let private_directory = directory.join(PRIVATE_DIRECTORY);
+        fs::create_dir_all(&directory).unwrap();
+        let _listener = bind_private(&path, &private_directory).unwrap();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(&private_directory), 0o700);
+        assert!(!private_directory.join(SOCKET_FILENAME).exists());
+        UnixStream::connect(&path).await.unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
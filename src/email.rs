@@ -12,6 +12,8 @@ use strfmt::strfmt;
 use thirtyfour::error::{WebDriverErrorInfo, WebDriverResult};
 use time::{macros::format_description, Date};
 use crate::errors::IncorrectCredentialsCount;
+use crate::messages::WebcomMessage;
+use crate::metrics::record_mail;
 use crate::{GenError, GenResult, ShiftState};
 
 use crate::{create_ical_filename, create_shift_link, set_get_name, Shift, SignInFailure};
@@ -142,6 +144,46 @@ fn find_send_shift_mails(
         &chrono::offset::Local::now().format("%d-%m-%Y").to_string(),
         DATE_DESCRIPTION,
     )?;
+    let current_shift_vec = compare_shifts(previous_shifts, new_shifts);
+    let mut new_shifts: Vec<&Shift> = current_shift_vec.iter().filter(|item| {
+        item.state == ShiftState::New
+    }).collect();
+    let mut updated_shifts: Vec<&Shift> = current_shift_vec.iter().filter(|item| {
+        item.state == ShiftState::Changed
+    }).collect();
+    let mut removed_shifts: Vec<&Shift> = current_shift_vec.iter().filter(|item| {
+        item.state == ShiftState::Deleted
+    }).collect();
+    // debug!("shift vec : {:#?}",current_shift_vec);
+    debug!("Removed shift vec size: {}", removed_shifts.len());
+    new_shifts.retain(|shift| shift.date >= current_date);
+    if !new_shifts.is_empty() && env.send_email_new_shift {
+        info!("Found {} new shifts, sending email", new_shifts.len());
+        create_send_new_email(mailer, new_shifts, env, false)?;
+    }
+    updated_shifts.retain(|shift| shift.date >= current_date);
+    if !updated_shifts.is_empty() && env.send_mail_updated_shift {
+        info!("Found {} updated shifts, sending email", updated_shifts.len());
+        create_send_new_email(mailer, updated_shifts, env, true)?;
+    }
+    if !removed_shifts.is_empty() && env.send_mail_updated_shift {
+        info!("Removing {} shifts", removed_shifts.len());
+        removed_shifts.retain(|shift| shift.date >= current_date);
+        if !removed_shifts.is_empty() {
+            send_removed_shifts_mail(mailer, env, removed_shifts)?;
+        }
+        
+    }
+    // At last remove all shifts marked as removed from the vec
+    let current_shift_vec = current_shift_vec.into_iter().filter(|shift| shift.state != ShiftState::Deleted).collect();
+    Ok(current_shift_vec)
+}
+
+/*
+Marks the new shifts as new, changed or unchanged by comparing them with the previous shifts
+Previous shifts that are not found again keep the deleted state they were given when loading
+*/
+pub fn compare_shifts(previous_shifts: Vec<Shift>, new_shifts: Vec<Shift>) -> Vec<Shift> {
     let mut previous_shifts_map  = previous_shifts.into_iter().map(|shift| {(shift.magic_number,shift)}).collect::<HashMap<i64,Shift>>();
     // Iterate through the current shifts to check for updates or new shifts
     // We start with a list of previously valid shifts. All marked as deleted
@@ -179,39 +221,7 @@ fn find_send_shift_mails(
             // They will be deleted
         }
     }
-    let current_shift_vec: Vec<Shift> = previous_shifts_map.into_values().collect();
-    let mut new_shifts: Vec<&Shift> = current_shift_vec.iter().filter(|item| {
-        item.state == ShiftState::New
-    }).collect();
-    let mut updated_shifts: Vec<&Shift> = current_shift_vec.iter().filter(|item| {
-        item.state == ShiftState::Changed
-    }).collect();
-    let mut removed_shifts: Vec<&Shift> = current_shift_vec.iter().filter(|item| {
-        item.state == ShiftState::Deleted
-    }).collect();
-    // debug!("shift vec : {:#?}",current_shift_vec);
-    debug!("Removed shift vec size: {}", removed_shifts.len());
-    new_shifts.retain(|shift| shift.date >= current_date);
-    if !new_shifts.is_empty() && env.send_email_new_shift {
-        info!("Found {} new shifts, sending email", new_shifts.len());
-        create_send_new_email(mailer, new_shifts, env, false)?;
-    }
-    updated_shifts.retain(|shift| shift.date >= current_date);
-    if !updated_shifts.is_empty() && env.send_mail_updated_shift {
-        info!("Found {} updated shifts, sending email", updated_shifts.len());
-        create_send_new_email(mailer, updated_shifts, env, true)?;
-    }
-    if !removed_shifts.is_empty() && env.send_mail_updated_shift {
-        info!("Removing {} shifts", removed_shifts.len());
-        removed_shifts.retain(|shift| shift.date >= current_date);
-        if !removed_shifts.is_empty() {
-            send_removed_shifts_mail(mailer, env, removed_shifts)?;
-        }
-        
-    }
-    // At last remove all shifts marked as removed from the vec
-    let current_shift_vec = current_shift_vec.into_iter().filter(|shift| shift.state != ShiftState::Deleted).collect();
-    Ok(current_shift_vec)
+    previous_shifts_map.into_values().collect()
 }
 
 /*
@@ -239,7 +249,10 @@ fn create_send_new_email(
     for shift in &new_shifts {
         let shift_table_clone = strfmt!(&shift_table,
             shift_number => shift.number.clone(),
-            shift_date => shift.date.format(DATE_DESCRIPTION)?.to_string(),
+            shift_date => match shift.holiday() {
+                Some(holiday) => format!("{} ({holiday})", shift.date.format(DATE_DESCRIPTION)?),
+                None => shift.date.format(DATE_DESCRIPTION)?.to_string(),
+            },
             shift_start => shift.start.format(TIME_DESCRIPTION)?.to_string(),
             shift_end => shift.end.format(TIME_DESCRIPTION)?.to_string(),
             shift_duration_hour => shift.duration.whole_hours().to_string(),
@@ -276,6 +289,10 @@ fn create_send_new_email(
         .header(ContentType::TEXT_HTML)
         .body(email_body_html)?;
     mailer.send(&email)?;
+    record_mail(match update {
+        true => "updated_shift",
+        false => "new_shift",
+    });
     Ok(())
 }
 
@@ -362,6 +379,7 @@ fn send_removed_shifts_mail(
         .header(ContentType::TEXT_HTML)
         .body(email_body_html)?;
     mailer.send(&email)?;
+    record_mail("removed_shift");
     Ok(())
 }
 
@@ -382,7 +400,7 @@ pub fn send_errors(errors: &Vec<GenError>, name: &str) -> GenResult<()> {
     let mailer = load_mailer(&env)?;
     let mut email_errors = "Er zijn fouten opgetreden tijdens het laden van shifts\n".to_string();
     for error in errors {
-        email_errors.push_str(&format!("Error: \n{}\n\n", error.to_string()));
+        email_errors.push_str(&format!("Error: \n{}\n\n", error.chain()));
     }
     let email = Message::builder()
         .from(format!("Foutje Berichtmans <{}>", &env.mail_from).parse()?)
@@ -391,6 +409,7 @@ pub fn send_errors(errors: &Vec<GenError>, name: &str) -> GenResult<()> {
         .header(ContentType::TEXT_PLAIN)
         .body(email_errors)?;
     mailer.send(&email)?;
+    record_mail("error");
     Ok(())
 }
 
@@ -413,6 +432,7 @@ pub fn send_gecko_error_mail<T: std::fmt::Debug>(error: WebDriverResult<T>) -> G
         .header(ContentType::TEXT_PLAIN)
         .body(email_errors)?;
     mailer.send(&email)?;
+    record_mail("gecko_error");
     Ok(())
 }
 
@@ -492,6 +512,7 @@ pub fn send_welcome_mail(
         .header(ContentType::TEXT_HTML)
         .body(email_body_html)?;
     mailer.send(&email)?;
+    record_mail("welcome");
     Ok(())
 }
 
@@ -557,6 +578,7 @@ pub fn send_failed_signin_mail(
         .header(ContentType::TEXT_HTML)
         .body(email_body_html)?;
     mailer.send(&email)?;
+    record_mail("failed_sign_in");
     Ok(())
 }
 
@@ -590,9 +612,70 @@ pub fn send_sign_in_succesful() -> GenResult<()> {
         .header(ContentType::TEXT_HTML)
         .body(email_body_html)?;
     mailer.send(&email)?;
+    record_mail("sign_in_succesful");
+    Ok(())
+}
+
+// Forwards messages webcom showed after signing in, as drivers might not open webcom themselves anymore
+pub fn send_webcom_messages_mail(messages: &[WebcomMessage]) -> GenResult<()> {
+    let send_webcom_messages = EnvMailVariables::str_to_bool(
+        &var("SEND_MAIL_WEBCOM_MESSAGES").unwrap_or("true".to_string()),
+    );
+    if !send_webcom_messages || messages.is_empty() {
+        return Ok(());
+    }
+
+    let base_html = fs::read_to_string("./templates/email_base.html").unwrap();
+    let messages_html = fs::read_to_string("./templates/webcom_messages.html").unwrap();
+    let message_html = fs::read_to_string("./templates/webcom_message.html").unwrap();
+    let name = set_get_name(None);
+    info!("Sending {} webcom message(s)", messages.len());
+    let env = EnvMailVariables::new(false)?;
+    let mailer = load_mailer(&env)?;
+    let mut message_tables = String::new();
+    for message in messages {
+        message_tables.push_str(&strfmt!(&message_html,
+            message_title => escape_html(&message.title),
+            message_date => escape_html(&message.date),
+            message_body => escape_html(&message.body).replace('\n', "<br>")
+        )?);
+    }
+    let single = messages.len() == 1;
+    let messages_html = strfmt!(&messages_html,
+        name => name.clone(),
+        is_zijn => if single { "is" } else { "zijn" }.to_string(),
+        message_ammount => messages.len().to_string(),
+        nieuw_nieuwe => if single { "nieuw" } else { "nieuwe" }.to_string(),
+        bericht_berichten => if single { "bericht" } else { "berichten" }.to_string(),
+        message_tables => message_tables
+    )?;
+    let email_body_html = strfmt!(&base_html,
+        content => messages_html,
+        banner_color => COLOR_BLUE,
+        footer => create_footer(false).unwrap_or_default()
+    )?;
+
+    let subject = match messages {
+        [message] => format!("Webcom bericht: {}", message.title),
+        _ => format!("{} nieuwe Webcom berichten", messages.len()),
+    };
+    let email = Message::builder()
+        .from(format!("WEBCOM ICAL <{}>", &env.mail_from).parse()?)
+        .to(format!("{} <{}>", name, &env.mail_to).parse()?)
+        .subject(subject)
+        .header(ContentType::TEXT_HTML)
+        .body(email_body_html)?;
+    mailer.send(&email)?;
+    record_mail("webcom_messages");
     Ok(())
 }
 
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
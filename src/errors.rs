@@ -1,15 +1,18 @@
 use std::{
+    error::Error,
     fmt::Display,
     fs::write,
     hash::{DefaultHasher, Hash, Hasher},
+    num::ParseIntError,
+    string::FromUtf8Error,
 };
 
 use dotenvy::var;
 use serde::{Deserialize, Serialize};
-use thirtyfour::{By, WebDriver};
+use thirtyfour::error::WebDriverError;
 use thiserror::Error;
 
-use crate::{GenResult, create_path, email};
+use crate::{GenResult, create_path, email, ical::CalendarVersionError};
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Error, Default)]
 pub enum SignInFailure {
@@ -43,31 +46,315 @@ pub enum FailureType {
     OK,
 }
 
+type BoxedError = Box<dyn Error + Send + Sync + 'static>;
+
+/*
+Every error webcom ical can run into
+The variant decides what happens next, like retrying, the heartbeat status and what is mailed
+The original error is kept as source, so the whole chain can be logged
+*/
+#[derive(Debug, Error)]
+pub enum WebcomError {
+    // Failures which decide the state of the run, like a failed sign in or no connection
+    #[error(transparent)]
+    Failure(#[from] FailureType),
+    #[error("Webcom kon niet uitgelezen worden: {message}")]
+    Scraping {
+        message: String,
+        #[source]
+        source: Option<BoxedError>,
+    },
+    // The offending text is kept, so it can be found in the logs and mails
+    #[error("Webcom tekst kon niet gelezen worden: {message}. Tekst: \"{text}\"")]
+    Parsing {
+        message: String,
+        text: String,
+        #[source]
+        source: Option<BoxedError>,
+    },
+    #[error("Opslaan of inlezen mislukt: {0}")]
+    Storage(#[source] BoxedError),
+    #[error("Mail versturen mislukt: {0}")]
+    Mail(#[source] BoxedError),
+    #[error("Kuma fout: {0}")]
+    Kuma(#[source] BoxedError),
+    #[error("Configuratie fout: {message}")]
+    Config {
+        message: String,
+        #[source]
+        source: Option<BoxedError>,
+    },
+    #[error(transparent)]
+    CalendarVersion(#[from] CalendarVersionError),
+    // A value which should have been there was not found
+    #[error("Niet gevonden: {0}")]
+    Missing(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl WebcomError {
+    pub fn scraping(message: impl Into<String>) -> Self {
+        Self::Scraping {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    pub fn config(message: impl Into<String>) -> Self {
+        Self::Config {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    // Turns any error into a parsing error of the given text, a parsing error which already has a text keeps it
+    pub fn with_text(self, text: &str) -> Self {
+        match self {
+            Self::Parsing {
+                message,
+                text: previous_text,
+                source,
+            } if previous_text.is_empty() => Self::Parsing {
+                message,
+                text: text.to_owned(),
+                source,
+            },
+            Self::Parsing { .. } | Self::Failure(_) => self,
+            error => Self::Parsing {
+                message: error.to_string(),
+                text: text.to_owned(),
+                source: Some(Box::new(error)),
+            },
+        }
+    }
+
+    // The failure of the run, if this error decides it
+    pub fn failure(&self) -> Option<&FailureType> {
+        match self {
+            Self::Failure(failure) => Some(failure),
+            _ => None,
+        }
+    }
+
+    // Every message in the source chain, to log or mail the whole error
+    pub fn chain(&self) -> String {
+        let mut text = self.to_string();
+        let mut source = self.source();
+        while let Some(error) = source {
+            text.push_str(&format!("\n  caused by: {error}"));
+            source = error.source();
+        }
+        text
+    }
+}
+
+impl From<&str> for WebcomError {
+    fn from(message: &str) -> Self {
+        Self::Other(message.to_owned())
+    }
+}
+
+impl From<String> for WebcomError {
+    fn from(message: String) -> Self {
+        Self::Other(message)
+    }
+}
+
+impl From<std::io::Error> for WebcomError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Storage(Box::new(error))
+    }
+}
+
+impl From<serde_json::Error> for WebcomError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Storage(Box::new(error))
+    }
+}
+
+impl From<dotenvy::Error> for WebcomError {
+    fn from(error: dotenvy::Error) -> Self {
+        Self::Config {
+            message: error.to_string(),
+            source: Some(Box::new(error)),
+        }
+    }
+}
+
+impl From<WebDriverError> for WebcomError {
+    fn from(error: WebDriverError) -> Self {
+        Self::Scraping {
+            message: error.to_string(),
+            source: Some(Box::new(error)),
+        }
+    }
+}
+
+impl From<reqwest::Error> for WebcomError {
+    fn from(error: reqwest::Error) -> Self {
+        Self::Scraping {
+            message: error.to_string(),
+            source: Some(Box::new(error)),
+        }
+    }
+}
+
+impl From<time::error::Parse> for WebcomError {
+    fn from(error: time::error::Parse) -> Self {
+        Self::Parsing {
+            message: error.to_string(),
+            text: String::new(),
+            source: Some(Box::new(error)),
+        }
+    }
+}
+
+impl From<ParseIntError> for WebcomError {
+    fn from(error: ParseIntError) -> Self {
+        Self::Parsing {
+            message: error.to_string(),
+            text: String::new(),
+            source: Some(Box::new(error)),
+        }
+    }
+}
+
+impl From<time::error::Format> for WebcomError {
+    fn from(error: time::error::Format) -> Self {
+        Self::Parsing {
+            message: error.to_string(),
+            text: String::new(),
+            source: Some(Box::new(error)),
+        }
+    }
+}
+
+impl From<time::error::ComponentRange> for WebcomError {
+    fn from(error: time::error::ComponentRange) -> Self {
+        Self::Parsing {
+            message: error.to_string(),
+            text: String::new(),
+            source: Some(Box::new(error)),
+        }
+    }
+}
+
+impl From<chrono::ParseError> for WebcomError {
+    fn from(error: chrono::ParseError) -> Self {
+        Self::Parsing {
+            message: error.to_string(),
+            text: String::new(),
+            source: Some(Box::new(error)),
+        }
+    }
+}
+
+impl From<FromUtf8Error> for WebcomError {
+    fn from(error: FromUtf8Error) -> Self {
+        Self::Parsing {
+            message: error.to_string(),
+            text: String::new(),
+            source: Some(Box::new(error)),
+        }
+    }
+}
+
+impl From<url::ParseError> for WebcomError {
+    fn from(error: url::ParseError) -> Self {
+        Self::Config {
+            message: error.to_string(),
+            source: Some(Box::new(error)),
+        }
+    }
+}
+
+impl From<tokio::time::error::Elapsed> for WebcomError {
+    fn from(error: tokio::time::error::Elapsed) -> Self {
+        Self::Scraping {
+            message: error.to_string(),
+            source: Some(Box::new(error)),
+        }
+    }
+}
+
+impl From<strfmt::FmtError> for WebcomError {
+    fn from(error: strfmt::FmtError) -> Self {
+        Self::Mail(Box::new(error))
+    }
+}
+
+impl From<rust_xlsxwriter::XlsxError> for WebcomError {
+    fn from(error: rust_xlsxwriter::XlsxError) -> Self {
+        Self::Storage(Box::new(error))
+    }
+}
+
+impl From<csv::Error> for WebcomError {
+    fn from(error: csv::Error) -> Self {
+        Self::Storage(Box::new(error))
+    }
+}
+
+impl From<lettre::error::Error> for WebcomError {
+    fn from(error: lettre::error::Error) -> Self {
+        Self::Mail(Box::new(error))
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for WebcomError {
+    fn from(error: lettre::transport::smtp::Error) -> Self {
+        Self::Mail(Box::new(error))
+    }
+}
+
+impl From<lettre::address::AddressError> for WebcomError {
+    fn from(error: lettre::address::AddressError) -> Self {
+        Self::Mail(Box::new(error))
+    }
+}
+
+impl From<kuma_client::error::Error> for WebcomError {
+    fn from(error: kuma_client::error::Error) -> Self {
+        Self::Kuma(Box::new(error))
+    }
+}
+
 pub trait OptionResult<T> {
-    fn result(self) -> GenResult<T>;
+    // what describes the value, like "shift number", and is shown in the error
+    fn result(self, what: &str) -> GenResult<T>;
 }
 
 impl<T> OptionResult<T> for Option<T> {
-    fn result(self) -> GenResult<T> {
+    fn result(self, what: &str) -> GenResult<T> {
         match self {
             Some(value) => Ok(value),
-            None => Err("Option Unwrap".into()),
+            None => Err(WebcomError::Missing(what.to_owned())),
         }
     }
 }
 
-pub async fn check_sign_in_error(driver: &WebDriver) -> GenResult<FailureType> {
+/*
+Decides if signing in worked, based on the page shown after signing in
+Being redirected to the messages page or getting a welcome header means it worked
+Otherwise the error banner tells why it failed
+*/
+pub fn check_sign_in_result(
+    on_messages_page: bool,
+    h3_text: Option<String>,
+    banner_text: Option<String>,
+) -> GenResult<()> {
+    if on_messages_page || h3_text.is_some() {
+        return Ok(());
+    }
     error!("Sign in failed");
-    match driver.find(By::Id("ctl00_lblMessage")).await {
-        Ok(element) => {
-            let element_text = element.text().await?;
-            let sign_in_error_type = get_sign_in_error_type(&element_text);
+    match banner_text {
+        Some(text) => {
+            let sign_in_error_type = get_sign_in_error_type(&text);
             info!("Found error banner: {:?}", &sign_in_error_type);
-            Ok(FailureType::SignInFailed(sign_in_error_type))
-        }
-        Err(_) => {
-            Err("Geen fout banner gevonden".into())
+            Err(FailureType::SignInFailed(sign_in_error_type).into())
         }
+        None => Err(WebcomError::scraping("Geen fout banner gevonden")),
     }
 }
 
@@ -151,8 +438,7 @@ impl IncorrectCredentialsCount {
             Some(SignInFailure::IncorrectCredentials) => {
                 info!("Permanently Skipping execution due to incorrect credentials");
                 self.error.clone()
-            
-            },
+            }
             _ => {
                 if self.retry_count % sign_in_attempt_reduce == 0 {
                     warn!(
@@ -219,18 +505,18 @@ where
     fn info(&self, function_name: &str) {
         match self {
             Err(err) => {
-                info!("Error in function \"{function_name}\": {}", err.to_string())
+                info!(function = function_name, error:% = err; "Error in function \"{function_name}\": {err}")
             }
             _ => (),
         }
     }
     fn warn_owned(self, function_name: &str) -> Self {
-        self.inspect_err(|err| warn!("Error in function \"{function_name}\": {}", err.to_string()))
+        self.inspect_err(|err| warn!(function = function_name, error:% = err; "Error in function \"{function_name}\": {err}"))
     }
     fn warn(&self, function_name: &str) {
         match self {
             Err(err) => {
-                warn!("Error in function \"{function_name}\": {}", err.to_string())
+                warn!(function = function_name, error:% = err; "Error in function \"{function_name}\": {err}")
             }
             _ => (),
         }
@@ -238,9 +524,30 @@ where
     fn error(&self, function_name: &str) {
         match self {
             Err(err) => {
-                error!("Error in function \"{function_name}\": {}", err.to_string())
+                error!(function = function_name, error:% = err; "Error in function \"{function_name}\": {err}")
             }
             _ => (),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing_errors_keep_the_webcom_text() {
+        let error: WebcomError = "12:xx".parse::<u8>().unwrap_err().into();
+        let error = error.with_text("Dienst: 1234 • Tijd: 12:xx");
+        assert!(matches!(
+            &error,
+            WebcomError::Parsing { text, .. } if text == "Dienst: 1234 • Tijd: 12:xx"
+        ));
+        assert!(error.chain().contains("caused by: invalid digit"));
+
+        let failure = WebcomError::from(FailureType::ConnectError).with_text("ignored");
+        assert_eq!(failure.failure(), Some(&FailureType::ConnectError));
+        let missing = None::<&str>.result("shift number").unwrap_err();
+        assert_eq!(missing.to_string(), "Niet gevonden: shift number");
+    }
+}
@@ -1,21 +1,20 @@
-use std::{fs::{self, read_to_string, write}, io::BufRead, os::unix::fs::PermissionsExt, time::Duration};
+use std::{fs::{read_to_string, write}, time::Duration};
 
-use chrono::{Timelike, Utc};
-use dotenvy::{dotenv_override, var};
-use ipipe::Pipe;
-use serde::Serialize;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Timelike, Utc};
+use dotenvy::var;
+use serde::{Deserialize, Serialize};
 use tokio::{sync::mpsc::Sender, time::sleep};
 
-use crate::{create_path, email::send_welcome_mail, errors::ResultLog, ical::get_ical_path, kuma, GenResult};
+use crate::{create_path, errors::ResultLog, health::ApplicationLogbook, status::save_next_run, polling::{adaptive_polling_enabled, max_backoff, shift_tomorrow, ChangeHistory, PollingMode}, schedule::Schedule, GenResult};
 
 type StartMinute = u8;
 
-#[derive(PartialEq, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum StartReason {
     Direct,
     Timer,
     Single,
-    Pipe,
+    Control,
     Force
 }
 
@@ -26,23 +25,31 @@ fn get_execution_properties() -> (Duration, StartMinute) {
             .parse::<u64>()?)
     }()
     .unwrap_or(7200);
-    let starting_minute = || -> GenResult<u8> {
+    (Duration::from_secs(cycle_time), get_starting_minute())
+}
+
+// The minute of the hour this user starts on, picked randomly once so not all users run at the same time
+pub fn get_starting_minute() -> StartMinute {
+    || -> GenResult<u8> {
         let path = create_path("starting_minute");
         let starting_minute_str =
             read_to_string(&path).unwrap_or(rand::random_range(0..60).to_string());
         _ = write(&path, starting_minute_str.as_bytes());
         Ok(starting_minute_str.parse()?)
     }()
-    .unwrap_or(rand::random_range(0..60));
-    (Duration::from_secs(cycle_time), starting_minute)
+    .unwrap_or(rand::random_range(0..60))
 }
 
 pub async fn execution_manager(tx: Sender<StartReason>, instant_run: bool) {
-    let execution_properties = get_execution_properties();
-    let current_time = Utc::now();
     if instant_run {
         _ = tx.send(StartReason::Direct).await;
     }
+    let schedule = Schedule::from_env();
+    if schedule.is_some() || adaptive_polling_enabled() {
+        return planned_execution(tx, schedule).await;
+    }
+    let execution_properties = get_execution_properties();
+    let current_time = Utc::now();
     if current_time.minute() != execution_properties.1 as u32 {
         let current_minute = current_time.minute() as i32;
         let mut waiting_minutes = execution_properties.1 as i32 - current_minute;
@@ -54,69 +61,134 @@ pub async fn execution_manager(tx: Sender<StartReason>, instant_run: bool) {
             waiting_minutes += 60;
         }
         debug!("Waiting {waiting_minutes} minutes until execution");
+        save_next_run(Local::now() + chrono::Duration::minutes(waiting_minutes as i64)).warn("Saving next run");
         sleep(Duration::from_secs(waiting_minutes as u64 * 60)).await;
     }
     
     loop {
         info!("Starting execution loop");
         _ = tx.try_send(StartReason::Timer);
-        
+        if let Ok(cycle_time) = chrono::Duration::from_std(execution_properties.0) {
+            save_next_run(Local::now() + cycle_time).warn("Saving next run");
+        }
         sleep(execution_properties.0).await;
     }
 }
 
-pub async fn start_pipe(tx: Sender<StartReason>) -> Result<(), ipipe::Error> {
-    let pipe_path = create_path("pipe");
-    if pipe_path.exists() {
-        info!("Previous pipe file found, removing");
-        std::fs::remove_file(&pipe_path).warn("Removing previous pipe");
+/*
+Runs at the times of the schedule, or every CYCLE_TIME without a schedule
+The next run is calculated after every run so changes to the clock are followed
+*/
+async fn planned_execution(tx: Sender<StartReason>, schedule: Option<Schedule>) {
+    if let Some(schedule) = &schedule {
+        info!("Using schedule {:?}", schedule.windows);
     }
-    let pipe = Pipe::open(&pipe_path, ipipe::OnCleanup::Delete).unwrap_or(Pipe::with_name("pipe")?);
-    if let Ok(metadata) =  fs::metadata(&pipe_path) {
-        let mut permissions = metadata.permissions();
-        permissions.set_mode(0o666);   
-        fs::set_permissions(&pipe_path, permissions).info("Setting permissions");
-    } else {
-        warn!("Failed to set permissions of pipe");
+    let cycle_time = get_execution_properties().0;
+    loop {
+        let now = Local::now();
+        let Some(next_run) = next_planned_run(now.naive_local(), schedule.as_ref(), cycle_time)
+            .and_then(|next_run| to_timezone(&Local, next_run))
+        else {
+            error!("Schedule has no next run, stopping the timer");
+            return;
+        };
+        let waiting_time = (next_run - now).to_std().unwrap_or_default();
+        debug!("Waiting until {next_run} for the next execution");
+        save_next_run(next_run).warn("Saving next run");
+        sleep(waiting_time).await;
+        info!("Starting planned execution");
+        _ = tx.try_send(StartReason::Timer);
     }
-    
-    let reader = std::io::BufReader::new(pipe);
-    // Depending on what message is sent from the pipe, changes the behaviour of the program
-    // q: Quit webcom ical
-    // f: Force the execution, ignoring incorrect credentials
-    // w: Send a welcome mail
-    // k: Run kuma logic
-    // p: Reload env variables and print the set password to the log
-    for line in reader.lines()
-    {
-        let start_reason = match &line {
-            Ok(line) if line == "q" => {return Ok(())},
-            Ok(line) if line == "f" => Some(StartReason::Force),
-            Ok(line) if line == "w" => {
-                let ical_path = get_ical_path().warn_owned("Getting ical path");
-                if let Ok(ical_path) = ical_path {
-                    send_welcome_mail(&ical_path, true).warn("Sending welcome mail");
-                }
-                None
-            }
-            Ok(line) if line == "k" => {
-                let username = var("USERNAME").expect("Error in username variable in pipe");
-                let kuma_url = var("KUMA_URL").ok();
-                if let Some(kuma_url) = kuma_url && !kuma_url.is_empty() {
-                    debug!("Checking if kuma needs to be created");
-                    kuma::first_run(&kuma_url, &username).await.warn("Kuma Run in pipe");
-                }
-                None
+}
+
+/*
+A time in the hour skipped when the clock is moved forward does not exist, the run is then an hour later
+So a window like 02:00-03:00 still runs on the last Sunday of March
+*/
+fn to_timezone<Tz: TimeZone>(timezone: &Tz, time: NaiveDateTime) -> Option<DateTime<Tz>> {
+    timezone.from_local_datetime(&time).earliest().or_else(|| {
+        debug!("{time} does not exist because of daylight saving time, running an hour later");
+        timezone.from_local_datetime(&(time + chrono::Duration::hours(1))).earliest()
+    })
+}
+
+/*
+With ADAPTIVE_POLLING the wait is shortened when changes are likely, and lengthened when webcom refuses or is down
+The logbook is of the last run which finished
+A run which would fall outside the windows of the schedule is moved to the next run of the schedule
+*/
+fn next_planned_run(now: NaiveDateTime, schedule: Option<&Schedule>, cycle_time: Duration) -> Option<NaiveDateTime> {
+    let normal_next_run = match schedule {
+        Some(schedule) => schedule.next_run(now)?,
+        None => now + chrono::Duration::from_std(cycle_time).ok()?,
+    };
+    if !adaptive_polling_enabled() {
+        return Some(normal_next_run);
+    }
+    let mode = PollingMode::decide(&ChangeHistory::load(), now, shift_tomorrow(now.date()), &ApplicationLogbook::load());
+    debug!("Polling mode: {mode:?}");
+    let normal_wait = (normal_next_run - now).to_std().unwrap_or_default();
+    let next_run = now + chrono::Duration::from_std(mode.wait(normal_wait, max_backoff())).ok()?;
+    match schedule {
+        Some(schedule) if !schedule.is_active(next_run) => schedule.next_run(next_run),
+        _ => Some(next_run),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, MappedLocalTime, NaiveDate};
+
+    // Europe/Amsterdam around the change to summer time on 30 March 2025, when 02:00 becomes 03:00
+    #[derive(Debug, Clone, Copy)]
+    struct SpringForward;
+
+    impl SpringForward {
+        fn at(hour: u32, minute: u32) -> NaiveDateTime {
+            NaiveDate::from_ymd_opt(2025, 3, 30).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+        }
+
+        fn offset(summer_time: bool) -> FixedOffset {
+            FixedOffset::east_opt(if summer_time { 7200 } else { 3600 }).unwrap()
+        }
+    }
+
+    impl TimeZone for SpringForward {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            SpringForward
+        }
+
+        fn offset_from_local_date(&self, _: &NaiveDate) -> MappedLocalTime<FixedOffset> {
+            MappedLocalTime::Single(Self::offset(false))
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> MappedLocalTime<FixedOffset> {
+            if *local < Self::at(2, 0) {
+                MappedLocalTime::Single(Self::offset(false))
+            } else if *local >= Self::at(3, 0) {
+                MappedLocalTime::Single(Self::offset(true))
+            } else {
+                MappedLocalTime::None
             }
-            Ok(line) if line == "p" => {
-                dotenv_override().warn("Loading ENV");
-                error!("Password is: {}",var("PASSWORD").warn_owned("Loading password").unwrap_or_default());
-                None},
-            Ok(_) => Some(StartReason::Pipe),
-            _ => None,
-        };
-        debug!("Recieved message from pipe: {}", line.unwrap_or("Error".to_owned()));
-        _ = start_reason.is_some_and(|start_reason| {tx.try_send(start_reason).info("Send start request from pipe");false}); 
+        }
+
+        fn offset_from_utc_date(&self, _: &NaiveDate) -> FixedOffset {
+            Self::offset(false)
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            Self::offset(*utc >= Self::at(1, 0))
+        }
     }
-    Ok(())
-}
\ No newline at end of file
+
+    #[test]
+    fn run_in_skipped_hour_is_an_hour_later() {
+        let next_run = to_timezone(&SpringForward, SpringForward::at(2, 30)).unwrap();
+        assert_eq!(next_run.naive_local(), SpringForward::at(3, 30));
+        let next_run = to_timezone(&SpringForward, SpringForward::at(1, 30)).unwrap();
+        assert_eq!(next_run.naive_local(), SpringForward::at(1, 30));
+    }
+}
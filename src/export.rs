@@ -0,0 +1,259 @@
+use std::{io::Write, path::PathBuf};
+
+use clap::ValueEnum;
+use rust_xlsxwriter::Workbook;
+use serde::Serialize;
+use time::{Date, macros::format_description};
+
+use crate::{
+    GenResult, Shift,
+    email::{DATE_DESCRIPTION, TIME_DESCRIPTION},
+    errors::WebcomError,
+    ical::load_stored_shifts,
+};
+
+const ISO_DATE_DESCRIPTION: &[time::format_description::BorrowedFormatItem<'_>] =
+    format_description!("[year]-[month]-[day]");
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    Xlsx,
+}
+
+// A single shift flattened into plain values, so every format has the same columns
+#[derive(Debug, Serialize)]
+struct ExportRow {
+    number: String,
+    kind: String,
+    location: String,
+    description: String,
+    start_date: String,
+    start_time: String,
+    end_date: String,
+    end_time: String,
+    duration: String,
+    duration_minutes: i64,
+    is_broken: bool,
+    broken_periods: String,
+    holiday: String,
+}
+
+impl ExportRow {
+    fn new(shift: &Shift) -> GenResult<Self> {
+        let broken_periods = shift
+            .broken_period
+            .iter()
+            .flatten()
+            .map(|period| {
+                Ok(format!(
+                    "{}-{}",
+                    period.0.format(TIME_DESCRIPTION)?,
+                    period.1.format(TIME_DESCRIPTION)?
+                ))
+            })
+            .collect::<GenResult<Vec<String>>>()?
+            .join(";");
+        Ok(Self {
+            number: shift.number.clone(),
+            kind: shift.kind.clone(),
+            location: shift.location.clone(),
+            description: shift.description.clone(),
+            start_date: shift.date.format(DATE_DESCRIPTION)?,
+            start_time: shift.start.format(TIME_DESCRIPTION)?,
+            end_date: shift.end_date.format(DATE_DESCRIPTION)?,
+            end_time: shift.end.format(TIME_DESCRIPTION)?,
+            duration: format!(
+                "{:02}:{:02}",
+                shift.duration.whole_hours(),
+                shift.duration.whole_minutes() % 60
+            ),
+            duration_minutes: shift.duration.whole_minutes(),
+            is_broken: shift.is_broken,
+            broken_periods,
+            holiday: shift
+                .holiday()
+                .map(|holiday| holiday.to_string())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+const HEADERS: [&str; 13] = [
+    "number",
+    "kind",
+    "location",
+    "description",
+    "start_date",
+    "start_time",
+    "end_date",
+    "end_time",
+    "duration",
+    "duration_minutes",
+    "is_broken",
+    "broken_periods",
+    "holiday",
+];
+
+// Parses a date given on the command line, eg: 2025-01-31
+pub fn parse_date_argument(text: &str) -> Result<Date, String> {
+    Date::parse(text, ISO_DATE_DESCRIPTION).map_err(|_| format!("Ongeldige datum: {text}"))
+}
+
+// Parses a month given on the command line, eg: 2025-01. Returns the first day of that month
+pub fn parse_month_argument(text: &str) -> Result<Date, String> {
+    parse_date_argument(&format!("{text}-01")).map_err(|_| format!("Ongeldige maand: {text}"))
+}
+
+fn shifts_between(shifts: Vec<Shift>, from: Option<Date>, to: Option<Date>) -> Vec<Shift> {
+    shifts
+        .into_iter()
+        .filter(|shift| from.is_none_or(|from| shift.date >= from))
+        .filter(|shift| to.is_none_or(|to| shift.date <= to))
+        .collect()
+}
+
+/*
+Exports all stored shifts between from and to (inclusive) in the requested format
+If no output path is given, CSV and JSON are written to stdout
+*/
+pub fn export_shifts(
+    format: ExportFormat,
+    from: Option<Date>,
+    to: Option<Date>,
+    output: Option<PathBuf>,
+) -> GenResult<()> {
+    let shifts = shifts_between(load_stored_shifts()?, from, to);
+    info!("Exporting {} shifts as {:?}", shifts.len(), format);
+    let rows = shifts
+        .iter()
+        .map(ExportRow::new)
+        .collect::<GenResult<Vec<ExportRow>>>()?;
+    let bytes = match format {
+        ExportFormat::Csv => create_csv(&rows)?,
+        ExportFormat::Json => serde_json::to_vec_pretty(&rows)?,
+        ExportFormat::Xlsx => create_xlsx(&rows)?,
+    };
+    match output {
+        Some(path) => std::fs::write(path, bytes)?,
+        None if format == ExportFormat::Xlsx => {
+            return Err(WebcomError::config(
+                "Excel export requires an output file (--output)",
+            ));
+        }
+        None => std::io::stdout().write_all(&bytes)?,
+    }
+    Ok(())
+}
+
+fn create_csv(rows: &[ExportRow]) -> GenResult<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    Ok(writer.into_inner().map_err(|error| error.into_error())?)
+}
+
+fn create_xlsx(rows: &[ExportRow]) -> GenResult<Vec<u8>> {
+    let mut workbook = Workbook::new();
+    let worksheet = workbook.add_worksheet().set_name("Rooster")?;
+    for (column, header) in HEADERS.iter().enumerate() {
+        worksheet.write_string(0, column as u16, *header)?;
+    }
+    for (index, row) in rows.iter().enumerate() {
+        let row_number = index as u32 + 1;
+        worksheet.write_string(row_number, 0, &row.number)?;
+        worksheet.write_string(row_number, 1, &row.kind)?;
+        worksheet.write_string(row_number, 2, &row.location)?;
+        worksheet.write_string(row_number, 3, &row.description)?;
+        worksheet.write_string(row_number, 4, &row.start_date)?;
+        worksheet.write_string(row_number, 5, &row.start_time)?;
+        worksheet.write_string(row_number, 6, &row.end_date)?;
+        worksheet.write_string(row_number, 7, &row.end_time)?;
+        worksheet.write_string(row_number, 8, &row.duration)?;
+        worksheet.write_number(row_number, 9, row.duration_minutes as f64)?;
+        worksheet.write_boolean(row_number, 10, row.is_broken)?;
+        worksheet.write_string(row_number, 11, &row.broken_periods)?;
+        worksheet.write_string(row_number, 12, &row.holiday)?;
+    }
+    worksheet.autofit();
+    Ok(workbook.save_to_buffer()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::{Month, macros::time};
+
+    fn date(day: u8) -> Date {
+        Date::from_calendar_date(2025, Month::June, day).unwrap()
+    }
+
+    #[test]
+    fn rows_contain_broken_periods_and_durations() {
+        let mut shift = Shift::test_shift(date(2), "G201");
+        shift.broken_period = Some(vec![
+            (time!(09:30), time!(11:00)),
+            (time!(12:00), time!(12:15)),
+        ]);
+        let row = ExportRow::new(&shift).unwrap();
+        assert_eq!(row.number, "G201");
+        assert_eq!(row.start_date, "02-06-2025");
+        assert_eq!(row.start_time, "06:00");
+        assert_eq!(row.end_time, "14:00");
+        assert_eq!(row.duration, "08:00");
+        assert_eq!(row.duration_minutes, 480);
+        assert!(row.is_broken);
+        assert_eq!(row.broken_periods, "09:30-11:00;12:00-12:15");
+        assert_eq!(row.holiday, "");
+    }
+
+    #[test]
+    fn shifts_are_filtered_by_from_and_to() {
+        let shifts: Vec<Shift> = (1..=5)
+            .map(|day| Shift::test_shift(date(day), &format!("V{day}")))
+            .collect();
+        let numbers = |shifts: Vec<Shift>| {
+            shifts
+                .into_iter()
+                .map(|shift| shift.number)
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            numbers(shifts_between(shifts.clone(), Some(date(2)), Some(date(4)))),
+            ["V2", "V3", "V4"]
+        );
+        assert_eq!(
+            numbers(shifts_between(shifts.clone(), None, Some(date(1)))),
+            ["V1"]
+        );
+        assert_eq!(numbers(shifts_between(shifts, Some(date(5)), None)), ["V5"]);
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        assert_eq!(parse_date_argument("2025-06-02"), Ok(date(2)));
+        assert_eq!(parse_month_argument("2025-06"), Ok(date(1)));
+        for text in ["", "02-06-2025", "2025-13-01", "2025-02-30", "morgen"] {
+            assert!(parse_date_argument(text).is_err(), "{text} was accepted");
+        }
+        for text in ["", "2025-13", "2025-06-02", "juni"] {
+            assert!(parse_month_argument(text).is_err(), "{text} was accepted");
+        }
+    }
+
+    #[test]
+    fn csv_columns_follow_the_headers() {
+        let rows = vec![
+            ExportRow::new(&Shift::test_shift(date(2), "V1")).unwrap(),
+            ExportRow::new(&Shift::test_shift(date(3), "V2")).unwrap(),
+        ];
+        let csv = String::from_utf8(create_csv(&rows).unwrap()).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], HEADERS.join(","));
+        assert!(lines[1].starts_with("V1,Rijdienst,Eindhoven,Lijn 401,02-06-2025,06:00,"));
+        assert!(lines[2].starts_with("V2,"));
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+use time::{Date, Month};
+
+use crate::{
+    FailureType, GenResult, SignInFailure,
+    errors::WebcomError,
+    messages::WebcomMessage,
+    shift::test_tooltip,
+    webcom_client::{CalendarDay, ShiftRows, WebcomClient},
+};
+
+/*
+An in-memory webcom, used to test everything above the WebcomClient without a browser or network
+Every response is scripted up front, months which are not scripted are empty
+*/
+#[derive(Debug, Default)]
+pub struct FakeClient {
+    pub connect_error: bool,
+    pub sign_in_failure: Option<SignInFailure>,
+    pub name: Option<String>,
+    // Shown after signing in, until they are acknowledged
+    pub messages: Vec<WebcomMessage>,
+    // The messages page can not be left, like when the button to mark them as read is not found
+    pub acknowledge_error: bool,
+    pub months: HashMap<(i32, Month), Vec<CalendarDay>>,
+    // How many times loading a month fails, before it works
+    pub failing_months: HashMap<(i32, Month), usize>,
+    pub shift_details: HashMap<Date, ShiftRows>,
+    // How often every operation has been called
+    pub opened: usize,
+    pub logins: usize,
+    pub acknowledged: usize,
+    pub fetched_months: Vec<(i32, Month)>,
+    pub fetched_shift_details: Vec<Date>,
+}
+
+impl FakeClient {
+    pub fn add_shift(&mut self, date: Date, number: &str, start: &str, end: &str) {
+        let tooltip = test_tooltip(date, number, start, end);
+        self.months
+            .entry((date.year(), date.month()))
+            .or_default()
+            .push(CalendarDay {
+                tooltip: Some(tooltip),
+                day_text: date.day().to_string(),
+            });
+    }
+
+    // A day which is not a shift, like a day off
+    pub fn add_day(&mut self, date: Date, tooltip: &str) {
+        self.months
+            .entry((date.year(), date.month()))
+            .or_default()
+            .push(CalendarDay {
+                tooltip: Some(tooltip.to_owned()),
+                day_text: date.day().to_string(),
+            });
+    }
+}
+
+impl WebcomClient for FakeClient {
+    async fn open(&mut self, _retry_count: usize) -> GenResult<()> {
+        self.opened += 1;
+        match self.connect_error {
+            true => Err(FailureType::ConnectError.into()),
+            false => Ok(()),
+        }
+    }
+
+    async fn login(&mut self, _user: &str, _pass: &str) -> GenResult<()> {
+        self.logins += 1;
+        match self.sign_in_failure.clone() {
+            Some(failure) => Err(FailureType::SignInFailed(failure).into()),
+            None => Ok(()),
+        }
+    }
+
+    async fn current_user_name(&mut self) -> GenResult<Option<String>> {
+        Ok(self.name.clone())
+    }
+
+    async fn pending_messages(&mut self) -> GenResult<Vec<WebcomMessage>> {
+        Ok(self.messages.clone())
+    }
+
+    async fn acknowledge_messages(&mut self) -> GenResult<()> {
+        if self.acknowledge_error {
+            return Err(WebcomError::scraping(
+                "Berichten pagina kon niet verlaten worden",
+            ));
+        }
+        if !self.messages.is_empty() {
+            self.acknowledged += 1;
+            self.messages.clear();
+        }
+        Ok(())
+    }
+
+    async fn fetch_month(&mut self, year: i32, month: Month) -> GenResult<Vec<CalendarDay>> {
+        self.fetched_months.push((year, month));
+        if let Some(failures) = self.failing_months.get_mut(&(year, month))
+            && *failures > 0
+        {
+            *failures -= 1;
+            return Err(format!("Month {year}-{} did not load", month as u8).into());
+        }
+        Ok(self.months.get(&(year, month)).cloned().unwrap_or_default())
+    }
+
+    async fn fetch_shift_detail(&mut self, date: Date) -> GenResult<ShiftRows> {
+        self.fetched_shift_details.push(date);
+        Ok(self.shift_details.get(&date).cloned().unwrap_or_default())
+    }
+
+    async fn quit(self) -> GenResult<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,379 @@
+use std::{
+    fs::{self, read_to_string, write},
+    path::{Path, PathBuf},
+};
+
+use dotenvy::var;
+use serde::{Deserialize, Serialize};
+use time::{Date, Duration};
+
+use crate::{
+    GenResult, Shift, ShiftState,
+    absence::{Absence, AbsenceCalendar},
+    create_path,
+    errors::ResultLog,
+    ical::{self, get_ical_path},
+};
+
+const CHANGES_FILENAME: &str = "shift_changes.json";
+const FREE_BUSY_ID_FILENAME: &str = "free_busy_id";
+// The id of the free/busy feed which is in SAVE_TARGET, so it can be removed when the id changes
+const FREE_BUSY_PUBLISHED_FILENAME: &str = "free_busy_published";
+
+/*
+A calendar next to the main calendar, with only one kind of event
+Users can subscribe to the feeds they want, and give them their own colour
+Feeds are enabled with a comma separated list in CALENDAR_FEEDS, e.g. CALENDAR_FEEDS=diensten,pauzes
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalendarFeed {
+    // Only the shifts, without absences
+    Shifts,
+    // The unpaid breaks of broken shifts
+    Breaks,
+    Absences,
+    // Shifts which have been added, changed or removed recently
+    Changes,
+    // Only when the user is working, to share with family. Has its own unguessable filename
+    FreeBusy,
+}
+
+impl CalendarFeed {
+    pub const ALL: [Self; 5] = [
+        Self::Shifts,
+        Self::Breaks,
+        Self::Absences,
+        Self::Changes,
+        Self::FreeBusy,
+    ];
+
+    // Used in CALENDAR_FEEDS and as the end of the filename
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Self::Shifts => "diensten",
+            Self::Breaks => "pauzes",
+            Self::Absences => "afwezigheid",
+            Self::Changes => "wijzigingen",
+            Self::FreeBusy => "bezet",
+        }
+    }
+
+    // The name shown in the calendar app
+    pub fn calendar_name(&self, name: &str) -> String {
+        match self {
+            Self::Shifts => format!("Hermes diensten - {name}"),
+            Self::Breaks => format!("Hermes pauzes - {name}"),
+            Self::Absences => format!("Hermes afwezigheid - {name}"),
+            Self::Changes => format!("Hermes wijzigingen - {name}"),
+            Self::FreeBusy => format!("Hermes bezet - {name}"),
+        }
+    }
+
+    /*
+    The feed is next to the main calendar, e.g. 1234_pauzes.ics
+    The free/busy feed is shared with others, so its name must not lead to the main calendar, e.g. 3f0c...9a1d.ics
+    */
+    pub fn path(&self) -> GenResult<PathBuf> {
+        let ical_path = get_ical_path()?;
+        if *self == Self::FreeBusy {
+            return Ok(ical_path.with_file_name(format!("{}.ics", free_busy_id())));
+        }
+        let stem = ical_path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default()
+            .to_owned();
+        Ok(ical_path.with_file_name(format!("{stem}_{}.ics", self.suffix())))
+    }
+
+    // ABSENCE_EVENTS=separate also enables the absences feed
+    pub fn enabled_from_env() -> Vec<Self> {
+        let feed_names = var("CALENDAR_FEEDS").unwrap_or_default();
+        Self::ALL
+            .into_iter()
+            .filter(|feed| {
+                feed_names
+                    .split(',')
+                    .any(|feed_name| feed_name.trim() == feed.suffix())
+                    || (*feed == Self::Absences
+                        && AbsenceCalendar::from_env() == AbsenceCalendar::Separate)
+            })
+            .collect()
+    }
+}
+
+// A shift which was added, changed or removed, and when webcom ical noticed it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShiftChange {
+    pub shift: Shift,
+    pub state: ShiftState,
+    pub detected: Date,
+}
+
+/*
+The recent changes to the roster, kept for CHANGES_FEED_DAYS days (14 by default)
+A shift which changes again replaces its earlier change on the same day
+*/
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ShiftChanges {
+    changes: Vec<ShiftChange>,
+}
+
+impl ShiftChanges {
+    pub fn load() -> Self {
+        match read_to_string(create_path(CHANGES_FILENAME)) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|error| {
+                warn!("Stored shift changes could not be read, starting over. Error: {error}");
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self) -> GenResult<()> {
+        write(
+            create_path(CHANGES_FILENAME),
+            serde_json::to_string_pretty(self)?.as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    /*
+    Records the new and changed shifts in current_shifts
+    Previous shifts which are not in the current shifts, and have no other shift on their day, have been removed
+    Returns how many changes were found
+    */
+    pub fn record(
+        &mut self,
+        previous_shifts: &[Shift],
+        current_shifts: &[Shift],
+        today: Date,
+    ) -> usize {
+        let removed_shifts = previous_shifts.iter().filter(|previous| {
+            !current_shifts.iter().any(|shift| {
+                shift.magic_number == previous.magic_number || shift.date == previous.date
+            })
+        });
+        let changes: Vec<ShiftChange> = current_shifts
+            .iter()
+            .filter(|shift| matches!(shift.state, ShiftState::New | ShiftState::Changed))
+            .map(|shift| (shift, shift.state.clone()))
+            .chain(removed_shifts.map(|shift| (shift, ShiftState::Deleted)))
+            .map(|(shift, state)| ShiftChange {
+                shift: shift.clone(),
+                state,
+                detected: today,
+            })
+            .collect();
+        self.changes.retain(|change| {
+            !changes
+                .iter()
+                .any(|new| new.shift.date == change.shift.date)
+        });
+        let change_count = changes.len();
+        self.changes.extend(changes);
+        self.changes
+            .sort_by_key(|change| (change.shift.date, change.shift.start));
+        change_count
+    }
+
+    // Forgets changes which were detected more than days ago
+    pub fn prune(&mut self, today: Date, days: i64) {
+        self.changes
+            .retain(|change| change.detected >= today - Duration::days(days));
+    }
+
+    pub fn changes(&self) -> &[ShiftChange] {
+        &self.changes
+    }
+}
+
+/*
+The random part of the filename of the free/busy feed, created once and kept in the kuma directory
+Removing the file creates a new filename, and the feed with the old filename is removed, so a shared link can be revoked
+*/
+fn free_busy_id() -> String {
+    if let Some(id) = read_free_busy_id(FREE_BUSY_ID_FILENAME) {
+        return id;
+    }
+    let id = format!("{:032x}", rand::random::<u128>());
+    write(create_path(FREE_BUSY_ID_FILENAME), id.as_bytes()).warn("Saving free/busy id");
+    id
+}
+
+// Only a hex id is used, so the file can never point outside SAVE_TARGET
+fn read_free_busy_id(filename: &str) -> Option<String> {
+    read_to_string(create_path(filename))
+        .ok()
+        .map(|id| id.trim().to_owned())
+        .filter(|id| !id.is_empty() && id.chars().all(|char| char.is_ascii_hexdigit()))
+}
+
+// The id of the published feed which must be removed, because the id changed or the feed was disabled
+fn revoked_free_busy_id(
+    published_id: Option<String>,
+    current_id: Option<String>,
+    enabled: bool,
+) -> Option<String> {
+    published_id.filter(|published_id| !enabled || current_id.as_ref() != Some(published_id))
+}
+
+// Removes the free/busy feed written by an earlier run if its link has been revoked
+fn remove_revoked_free_busy_feed(ical_path: &Path, enabled: bool) {
+    let Some(revoked_id) = revoked_free_busy_id(
+        read_free_busy_id(FREE_BUSY_PUBLISHED_FILENAME),
+        read_free_busy_id(FREE_BUSY_ID_FILENAME),
+        enabled,
+    ) else {
+        return;
+    };
+    let path = ical_path.with_file_name(format!("{revoked_id}.ics"));
+    if path.exists() {
+        info!("Removing revoked free/busy feed {:?}", &path);
+        fs::remove_file(&path).warn("Removing free/busy feed");
+    }
+    fs::remove_file(create_path(FREE_BUSY_PUBLISHED_FILENAME))
+        .warn("Removing free/busy published id");
+}
+
+// The title of every event in the free/busy feed, set with FREE_BUSY_TITLE
+pub fn free_busy_title() -> String {
+    var("FREE_BUSY_TITLE")
+        .ok()
+        .filter(|title| !title.trim().is_empty())
+        .unwrap_or("Bezet".to_owned())
+}
+
+pub fn changes_feed_days() -> i64 {
+    var("CHANGES_FEED_DAYS")
+        .ok()
+        .and_then(|days| days.parse().ok())
+        .unwrap_or(14)
+}
+
+/*
+Writes every enabled feed, and removes the files of feeds which are not enabled anymore
+split_shifts are the shifts as they are in the main calendar
+*/
+pub fn write_feeds(
+    split_shifts: &[Shift],
+    all_shifts: &[Shift],
+    absences: &[Absence],
+    changes: &[ShiftChange],
+) -> GenResult<()> {
+    let enabled_feeds = CalendarFeed::enabled_from_env();
+    remove_revoked_free_busy_feed(
+        &get_ical_path()?,
+        enabled_feeds.contains(&CalendarFeed::FreeBusy),
+    );
+    for feed in CalendarFeed::ALL {
+        if !enabled_feeds.contains(&feed) {
+            // The free/busy feed has already been removed, and must not get an id when it is disabled
+            if feed == CalendarFeed::FreeBusy {
+                continue;
+            }
+            let path = feed.path()?;
+            if path.exists() {
+                info!("Removing disabled feed {:?}", &path);
+                fs::remove_file(&path).warn("Removing calendar feed");
+            }
+            continue;
+        }
+        let calendar = match feed {
+            CalendarFeed::Shifts => ical::create_shifts_feed(split_shifts),
+            CalendarFeed::Breaks => ical::create_breaks_feed(all_shifts),
+            CalendarFeed::Absences => ical::create_absences_feed(absences),
+            CalendarFeed::Changes => ical::create_changes_feed(changes),
+            CalendarFeed::FreeBusy => ical::create_free_busy_feed(split_shifts),
+        };
+        let path = feed.path()?;
+        info!("Writing {} feed to: {:?}", feed.suffix(), &path);
+        write(path, calendar.as_bytes())?;
+        if feed == CalendarFeed::FreeBusy {
+            write(create_path(FREE_BUSY_PUBLISHED_FILENAME), free_busy_id())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Month;
+
+    fn shift(day: u8, number: &str, state: ShiftState) -> Shift {
+        let date = Date::from_calendar_date(2025, Month::June, day).unwrap();
+        let mut shift = Shift::test_shift(date, number);
+        shift.state = state;
+        shift
+    }
+
+    #[test]
+    fn changes_are_recorded_and_pruned() {
+        let today = Date::from_calendar_date(2025, Month::June, 1).unwrap();
+        let previous = vec![
+            shift(2, "V1", ShiftState::Deleted),
+            shift(3, "V2", ShiftState::Deleted),
+            shift(4, "V3", ShiftState::Deleted),
+        ];
+        let current = vec![
+            shift(2, "V1", ShiftState::Unchanged),
+            shift(3, "V9", ShiftState::Changed),
+            shift(5, "V4", ShiftState::New),
+        ];
+        let mut changes = ShiftChanges::default();
+        changes.record(&previous, &current, today);
+        let recorded: Vec<(&str, &ShiftState)> = changes
+            .changes()
+            .iter()
+            .map(|change| (change.shift.number.as_str(), &change.state))
+            .collect();
+        assert_eq!(
+            recorded,
+            [
+                ("V9", &ShiftState::Changed),
+                ("V3", &ShiftState::Deleted),
+                ("V4", &ShiftState::New)
+            ]
+        );
+
+        changes.record(
+            &[],
+            &[shift(3, "V8", ShiftState::Changed)],
+            today + Duration::days(10),
+        );
+        assert_eq!(changes.changes().len(), 3);
+        changes.prune(today + Duration::days(15), 14);
+        assert_eq!(changes.changes().len(), 1);
+        assert_eq!(changes.changes()[0].shift.number, "V8");
+    }
+
+    #[test]
+    fn revoked_free_busy_feed_is_removed() {
+        let id = |id: &str| Some(id.to_owned());
+        assert_eq!(revoked_free_busy_id(id("aa"), id("aa"), true), None);
+        assert_eq!(revoked_free_busy_id(id("aa"), id("bb"), true), id("aa"));
+        assert_eq!(revoked_free_busy_id(id("aa"), None, true), id("aa"));
+        assert_eq!(revoked_free_busy_id(id("aa"), id("aa"), false), id("aa"));
+        assert_eq!(revoked_free_busy_id(None, id("aa"), false), None);
+    }
+
+    #[test]
+    fn free_busy_feed_only_has_busy_blocks() {
+        let calendar = ical::create_free_busy_feed(&[shift(2, "V1", ShiftState::Unchanged)]);
+        assert!(calendar.contains("SUMMARY:Bezet"));
+        assert!(calendar.contains("DTSTART;TZID=Europe/Amsterdam:20250602T060000"));
+        for hidden in [
+            "V1",
+            "DESCRIPTION",
+            "LOCATION",
+            "X-BUSSIE-METADATA",
+            "shift",
+        ] {
+            assert!(
+                !calendar.contains(hidden),
+                "{hidden} is in the free/busy feed"
+            );
+        }
+    }
+}
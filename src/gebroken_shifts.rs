@@ -1,12 +1,12 @@
 use crate::{
-    email::{DATE_DESCRIPTION, TIME_DESCRIPTION}, errors::ResultLog, shift::ShiftState, GenResult, Shift
+    GenResult, Shift,
+    email::TIME_DESCRIPTION,
+    errors::{OptionResult, ResultLog},
+    page_cache::PageCache,
+    shift::ShiftState,
+    webcom_client::{ShiftRows, WebcomClient},
 };
 use dotenvy::var;
-use thirtyfour::{
-    WebDriver, WebElement,
-    error::WebDriverResult,
-    prelude::*,
-};
 use time::{Duration, Time};
 
 /*
@@ -18,8 +18,9 @@ Returns the new list
 Does not return most errors as there are a few valid reason this function fails
 */
 pub async fn load_broken_shift_information(
-    driver: &WebDriver,
+    client: &mut impl WebcomClient,
     all_shifts: &Vec<Shift>,
+    page_cache: &mut PageCache,
 ) -> GenResult<Vec<Shift>> {
     let mut shifts_clone = all_shifts.clone();
     for shift in shifts_clone.iter_mut() {
@@ -27,12 +28,19 @@ pub async fn load_broken_shift_information(
             continue;
         }
         // Try to load the broken shift information. If it fails, that is not important
-        if shift.broken_period.is_none(){
-            info!("Creating broken shift: {}", shift.number);
-            load_single_broken_info(driver, shift).await?;
-        } else if matches!(shift.state, ShiftState::Changed | ShiftState::New) {
+        if shift.broken_period.is_none()
+            || matches!(shift.state, ShiftState::Changed | ShiftState::New)
+        {
+            if let Some(broken_period) = page_cache.get_unchanged_broken_period(shift) {
+                info!(
+                    "Month of broken shift {} has not changed, using previous broken times",
+                    shift.number
+                );
+                shift.broken_period = Some(broken_period);
+                continue;
+            }
             info!("Creating broken shift: {}", shift.number);
-            load_single_broken_info(driver, shift).await?;
+            load_single_broken_info(client, shift, page_cache).await;
         } else {
             info!(
                 "Shift {} is broken, but unchanged from last check",
@@ -45,10 +53,11 @@ pub async fn load_broken_shift_information(
 }
 
 async fn load_single_broken_info(
-    driver: &WebDriver,
+    client: &mut impl WebcomClient,
     shift: &mut Shift,
-) -> GenResult<()> {
-    match get_broken_shift_time(driver, shift).await {
+    page_cache: &mut PageCache,
+) {
+    match get_broken_shift_time(client, shift, page_cache).await {
         Ok(_) => {
             info!("Added broken shift time to shift {}", shift.number);
         }
@@ -60,17 +69,25 @@ async fn load_single_broken_info(
             );
         }
     };
-    navigate_to_subdirectory(driver, "/WebComm/roster.aspx").await?; //Ga terug naar de rooster pagina, anders laden de gebroken shifts niet goed
-    wait_for_response(driver, By::ClassName("calDay"), false).await?;
-    Ok(())
 }
 
 /*
 A small function to combine the three functions needed for creating a broken shift into one match statement
 */
-async fn get_broken_shift_time(driver: &WebDriver, shift: &mut Shift) -> GenResult<()> {
-    let broken_diensten = load_broken_dienst_page(driver, &shift).await?;
-    let between_times = find_broken_start_stop_time(broken_diensten).await?;
+async fn get_broken_shift_time(
+    client: &mut impl WebcomClient,
+    shift: &mut Shift,
+    page_cache: &mut PageCache,
+) -> GenResult<()> {
+    let shift_rows = client.fetch_shift_detail(shift.date).await?;
+    let page_hash = PageCache::hash_shift_page(&shift_rows);
+    if let Some(between_times) = page_cache.get_shift_page(shift.date, page_hash) {
+        debug!("Shift page of {} has not changed", shift.number);
+        shift.broken_period = Some(between_times);
+        return Ok(());
+    }
+    let between_times = find_broken_start_stop_time(&shift_rows);
+    page_cache.store_shift_page(shift.date, page_hash, &between_times);
     shift.broken_period = Some(between_times);
     Ok(())
 }
@@ -79,18 +96,22 @@ async fn get_broken_shift_time(driver: &WebDriver, shift: &mut Shift) -> GenResu
 Looks for a time difference between one event in the shift info and the next
 If something boes wrong, skip it. 
 */
-pub async fn find_broken_start_stop_time(shift_rows: Vec<WebElement>) -> GenResult<Vec<(Time,Time)>> {
+pub fn find_broken_start_stop_time(shift_rows: &ShiftRows) -> Vec<(Time, Time)> {
     let mut broken_periods: Vec<(Time, Time)> = vec![];
     let mut previous_element_end_time = None;
-    for activity in shift_rows {
-        let activity_columns = activity.query(By::Tag("td")).all_from_selector().await?;
-        let (activity_start_time, activity_end_time) = match async || -> GenResult<(Time, Time)> {
+    for activity_columns in shift_rows {
+        let (activity_start_time, activity_end_time) = match || -> GenResult<(Time, Time)> {
             Ok((
-                Time::parse(&activity_columns[1].text().await?, TIME_DESCRIPTION)?,
-                Time::parse(&activity_columns[3].text().await?, TIME_DESCRIPTION)?,
+                Time::parse(
+                    activity_columns.get(1).result("activity start time")?,
+                    TIME_DESCRIPTION,
+                )?,
+                Time::parse(
+                    activity_columns.get(3).result("activity end time")?,
+                    TIME_DESCRIPTION,
+                )?,
             ))
         }()
-        .await
         .warn_owned("Getting broken shift element time")
         {
             Ok(times) => times,
@@ -110,7 +131,7 @@ pub async fn find_broken_start_stop_time(shift_rows: Vec<WebElement>) -> GenResu
         previous_element_end_time = Some(activity_end_time);
     }
     debug!("Broken periods found: {broken_periods:?}");
-    Ok(broken_periods)
+    broken_periods
 }
 
 /*
@@ -162,37 +183,6 @@ pub fn stop_shift_at_midnight(shifts: &Vec<Shift>) -> Vec<Shift> {
     temp_shifts
 }
 
-/*
-Creates the URL needed for the broken shift
-Waits untill the page is fully loaded and then gets and returns all rows of the shift sheet
-*/
-pub async fn load_broken_dienst_page(
-    driver: &WebDriver,
-    shift: &Shift,
-) -> GenResult<Vec<WebElement>> {
-    let date = shift.date;
-    let formatted_date = date.format(DATE_DESCRIPTION)?;
-    navigate_to_subdirectory(driver, &format!("/WebComm/shift.aspx?{}", formatted_date)).await?;
-    //wait_until_loaded(&driver).await?;
-    wait_for_response(driver, By::PartialLinkText("Werk en afwezigheden"), true).await?;
-    let trip_body = driver.find(By::Tag("tbody")).await?;
-    let trip_rows = trip_body.query(By::Tag("tr")).all_from_selector().await?;
-    Ok(trip_rows)
-}
-
-/*
-A function to navigate to a subdirectory of the current URL
-Needed because if the while url is entered, the cookies will be lost and you will have to log in again
-*/
-pub async fn navigate_to_subdirectory(
-    driver: &WebDriver,
-    subdirectory: &str,
-) -> WebDriverResult<()> {
-    let script = format!("window.location.href = '{}';", subdirectory);
-    driver.execute(&script, vec![]).await?;
-    Ok(())
-}
-
 // This function clones a vec of shifts and splits broken shifts, if that value is set
 pub fn split_broken_shifts(shifts: &Vec<Shift>) -> Vec<Shift> {
     let mut shifts_clone: Vec<Shift> = shifts.iter().cloned().collect();
@@ -215,20 +205,52 @@ pub fn split_broken_shifts(shifts: &Vec<Shift>) -> Vec<Shift> {
     shifts_clone
 }
 
-/*
-A simple function to wait until a page is truly fully loaded
-You need to provide a element on the page to wait for
-If clickable is false it will only check if it is displayed, not clickable
-*/
-pub async fn wait_for_response(
-    driver: &WebDriver,
-    element: By,
-    clickable: bool,
-) -> WebDriverResult<()> {
-    let query = driver.query(element.clone()).first().await?;
-    match clickable {
-        true => query.wait_until().wait(std::time::Duration::from_secs(60), std::time::Duration::from_secs(1)).clickable().await?,
-        false => query.wait_until().wait(std::time::Duration::from_secs(60), std::time::Duration::from_secs(1)).displayed().await?,
-    };
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_client::FakeClient;
+    use time::{Date, Month};
+
+    fn row(start: &str, end: &str) -> Vec<String> {
+        vec!["Rit".into(), start.into(), "Eindhoven".into(), end.into()]
+    }
+
+    #[tokio::test]
+    async fn broken_periods_are_loaded_from_shift_page() {
+        let date = Date::from_calendar_date(2025, Month::June, 2).unwrap();
+        let mut client = FakeClient::default();
+        client.add_shift(date, "G2309", "06:00", "18:00");
+        client.shift_details.insert(
+            date,
+            vec![
+                row("06:00", "09:30"),
+                row("09:35", "10:00"),
+                row("14:00", "18:00"),
+            ],
+        );
+        let tooltip = client.months[&(2025, Month::June)][0].tooltip.clone();
+        let shift = Shift::new(tooltip.unwrap(), date).unwrap();
+        let shifts =
+            load_broken_shift_information(&mut client, &vec![shift], &mut PageCache::default())
+                .await
+                .unwrap();
+        assert_eq!(
+            shifts[0].broken_period,
+            Some(vec![(
+                Time::from_hms(10, 0, 0).unwrap(),
+                Time::from_hms(14, 0, 0).unwrap()
+            )])
+        );
+        assert_eq!(client.fetched_shift_details, vec![date]);
+    }
+
+    #[test]
+    fn rows_without_times_are_skipped() {
+        let rows = vec![
+            row("06:00", "09:30"),
+            vec!["Pauze".into()],
+            row("14:00", "18:00"),
+        ];
+        assert!(find_broken_start_stop_time(&rows).is_empty());
+    }
 }
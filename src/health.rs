@@ -5,10 +5,15 @@ use std::{
 };
 
 use serde::{Deserialize, Serialize};
+use time::Month;
 use url::Url;
 
 use crate::{
-    errors::SignInFailure, ical::{get_ical_path, load_ical_file, CALENDAR_VERSION}, shift::Shift, FailureType, GenResult, BASE_DIRECTORY
+    BASE_DIRECTORY, FailureType, GenResult,
+    errors::SignInFailure,
+    ical::{CALENDAR_VERSION, get_ical_path, load_ical_file},
+    page_cache::PageCacheStatistics,
+    shift::Shift,
 };
 
 #[derive(Default, Debug, Serialize, Deserialize, PartialEq, Clone)]
@@ -50,6 +55,10 @@ impl ApplicationLogbook {
         self.application_state.shifts = number_of_shifts;
         self.application_state.non_relevant_shifts = non_relevant_shifts as u64;
         self.application_state.failed_broken_shifts = number_of_failed_broken_shifts;
+        self.application_state.holiday_shifts = shifts
+            .iter()
+            .filter(|shift| shift.holiday().is_some())
+            .count() as u64;
     }
 
     pub fn add_failed_shifts(&mut self, number: u64, replace: bool) {
@@ -59,6 +68,30 @@ impl ApplicationLogbook {
         }
     }
 
+    pub fn clear_month_statistics(&mut self) {
+        self.application_state.months.clear();
+    }
+
+    pub fn add_month_statistics(
+        &mut self,
+        year: i32,
+        month: Month,
+        shifts: u64,
+        failed_shifts: u64,
+        error: Option<String>,
+    ) {
+        self.application_state.months.push(MonthStatistics {
+            month: format!("{year}-{:02}", month as u8),
+            shifts,
+            failed_shifts,
+            error,
+        });
+    }
+
+    pub fn set_page_cache_statistics(&mut self, statistics: &PageCacheStatistics) {
+        self.application_state.page_cache = statistics.clone();
+    }
+
     // Populate the logbook values and save it to disk
     pub fn save(&mut self, state: &FailureType) -> GenResult<()> {
         let path = ApplicationLogbook::create_path();
@@ -98,7 +131,22 @@ pub struct ApplicationState {
     pub non_relevant_shifts: u64,
     pub failed_shifts: u64,
     pub failed_broken_shifts: u64,
+    #[serde(default)]
+    pub holiday_shifts: u64,
     pub calendar_version: String,
+    #[serde(default)]
+    pub months: Vec<MonthStatistics>,
+    #[serde(default)]
+    pub page_cache: PageCacheStatistics,
+}
+
+// How loading a single month of the roster went
+#[derive(Default, Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct MonthStatistics {
+    pub month: String,
+    pub shifts: u64,
+    pub failed_shifts: u64,
+    pub error: Option<String>,
 }
 
 pub async fn send_heartbeat(
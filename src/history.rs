@@ -0,0 +1,297 @@
+use std::{
+    fs::{OpenOptions, read_to_string, write},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Duration, Local};
+use dotenvy::var;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    FailureType, GenResult, create_path,
+    execution::StartReason,
+    health::ApplicationLogbook,
+    logging,
+    retry::{CircuitDecision, RetryStep},
+};
+
+const HISTORY_FILENAME: &str = "history.jsonl";
+
+// A single finished run, appended as one JSON line to kuma/history.jsonl
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunRecord {
+    // RFC 3339
+    pub timestamp: String,
+    // The run id in the logs, for runs before structured logging it is empty
+    #[serde(default)]
+    pub run_id: Option<String>,
+    pub start_reason: StartReason,
+    pub outcome: FailureType,
+    pub duration_ms: u64,
+    // How many tries of the run failed
+    pub retries: usize,
+    pub errors: Vec<String>,
+    // Every failed try, with its failure class and the wait before the next try
+    #[serde(default)]
+    pub retry_steps: Vec<RetryStep>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker: Option<CircuitDecision>,
+    pub shifts: u64,
+    pub broken_shifts: u64,
+    pub non_relevant_shifts: u64,
+    pub failed_shifts: u64,
+}
+
+impl RunRecord {
+    // Created after the logbook of the run is saved, so the duration and shift statistics are filled in
+    pub fn new(
+        start_reason: StartReason,
+        outcome: &FailureType,
+        logbook: &ApplicationLogbook,
+    ) -> Self {
+        let state = &logbook.application_state;
+        Self {
+            timestamp: Local::now().to_rfc3339(),
+            run_id: logging::run_id(),
+            start_reason,
+            outcome: outcome.clone(),
+            duration_ms: state.execution_time_ms,
+            retries: 0,
+            errors: vec![],
+            retry_steps: vec![],
+            circuit_breaker: None,
+            shifts: state.shifts,
+            broken_shifts: state.broken_shifts,
+            non_relevant_shifts: state.non_relevant_shifts,
+            failed_shifts: state.failed_shifts,
+        }
+    }
+
+    fn time(&self) -> Option<DateTime<Local>> {
+        DateTime::parse_from_rfc3339(&self.timestamp)
+            .ok()
+            .map(|time| time.with_timezone(&Local))
+    }
+
+    pub fn is_failure(&self) -> bool {
+        self.outcome != FailureType::OK
+    }
+
+    fn human(&self) -> String {
+        let time = self
+            .time()
+            .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or(self.timestamp.clone());
+        let mut line = format!(
+            "{time}  {:<7}  {:.1}s  {} shifts ({} broken, {} failed)  retries: {}  {}",
+            format!("{:?}", self.start_reason),
+            self.duration_ms as f64 / 1000.0,
+            self.shifts,
+            self.broken_shifts,
+            self.failed_shifts,
+            self.retries,
+            self.outcome,
+        );
+        for step in &self.retry_steps {
+            let decision = match step.delay_seconds {
+                Some(delay) => format!("tried again after {delay:.1}s"),
+                None => "gave up".to_owned(),
+            };
+            line.push_str(&format!(
+                "\n    try {} {:?}, {decision}: {}",
+                step.attempt + 1,
+                step.class,
+                step.error
+            ));
+        }
+        match &self.circuit_breaker {
+            Some(CircuitDecision::Paused { until }) => line.push_str(&format!(
+                "\n    skipped, webcom is down, paused until {until}"
+            )),
+            Some(CircuitDecision::Opened { until }) => {
+                line.push_str(&format!("\n    webcom is down, paused until {until}"))
+            }
+            None => (),
+        }
+        line
+    }
+}
+
+fn history_path() -> PathBuf {
+    create_path(HISTORY_FILENAME)
+}
+
+// Runs older than HISTORY_RETENTION_DAYS are removed, 0 keeps every run
+fn retention() -> Option<Duration> {
+    let days = var("HISTORY_RETENTION_DAYS")
+        .ok()
+        .and_then(|days| days.parse::<i64>().ok())
+        .unwrap_or(90);
+    (days > 0).then(|| Duration::days(days))
+}
+
+// Lines which can not be read, for example from a newer version, are skipped
+fn load_from(path: &Path) -> Vec<RunRecord> {
+    read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+pub fn load_history() -> Vec<RunRecord> {
+    load_from(&history_path())
+}
+
+/*
+Appends the run to the history
+If the oldest run is past the retention, the history is rewritten without the expired runs
+Lines which can not be read are kept as they are, they could be from a newer version
+*/
+fn append_to(path: &Path, record: &RunRecord, retention: Option<Duration>) -> GenResult<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    let Some(retention) = retention else {
+        return Ok(());
+    };
+    let oldest_allowed = Local::now() - retention;
+    let content = read_to_string(path)?;
+    let is_expired = |line: &str| {
+        serde_json::from_str::<RunRecord>(line)
+            .ok()
+            .and_then(|record| record.time())
+            .is_some_and(|time| time < oldest_allowed)
+    };
+    if content.lines().any(is_expired) {
+        let kept: Vec<&str> = content
+            .lines()
+            .filter(|line| !line.is_empty() && !is_expired(line))
+            .collect();
+        write(path, format!("{}\n", kept.join("\n")))?;
+    }
+    Ok(())
+}
+
+pub fn append_run(record: &RunRecord) -> GenResult<()> {
+    append_to(&history_path(), record, retention())
+}
+
+fn query(history: Vec<RunRecord>, since: DateTime<Local>, failures: bool) -> Vec<RunRecord> {
+    history
+        .into_iter()
+        .filter(|record| record.time().is_some_and(|time| time >= since))
+        .filter(|record| !failures || record.is_failure())
+        .collect()
+}
+
+// The history command, prints the runs of the last days, in a human readable form or as JSON lines
+pub fn print_history(days: i64, failures: bool, json: bool) -> GenResult<()> {
+    let records = query(
+        load_history(),
+        Local::now() - Duration::days(days),
+        failures,
+    );
+    if json {
+        for record in &records {
+            println!("{}", serde_json::to_string(record)?);
+        }
+        return Ok(());
+    }
+    for record in &records {
+        println!("{}", record.human());
+    }
+    let failed = records.iter().filter(|record| record.is_failure()).count();
+    println!(
+        "{} runs in the last {days} days, {failed} failed",
+        records.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(timestamp: DateTime<Local>, outcome: FailureType) -> RunRecord {
+        RunRecord {
+            timestamp: timestamp.to_rfc3339(),
+            run_id: None,
+            start_reason: StartReason::Timer,
+            outcome,
+            duration_ms: 1500,
+            retries: 0,
+            errors: vec![],
+            retry_steps: vec![],
+            circuit_breaker: None,
+            shifts: 40,
+            broken_shifts: 2,
+            non_relevant_shifts: 100,
+            failed_shifts: 0,
+        }
+    }
+
+    #[test]
+    fn expired_runs_are_removed() {
+        let path =
+            std::env::temp_dir().join(format!("webcom_history_{}.jsonl", std::process::id()));
+        _ = std::fs::remove_file(&path);
+        let now = Local::now();
+        let old = record(now - Duration::days(100), FailureType::OK);
+        let recent = record(now - Duration::days(1), FailureType::ConnectError);
+        append_to(&path, &old, None).unwrap();
+        append_to(&path, &recent, None).unwrap();
+        assert_eq!(load_from(&path).len(), 2);
+        append_to(
+            &path,
+            &record(now, FailureType::OK),
+            Some(Duration::days(90)),
+        )
+        .unwrap();
+        let history = load_from(&path);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0], recent);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unreadable_lines_are_kept() {
+        let path = std::env::temp_dir().join(format!(
+            "webcom_history_unreadable_{}.jsonl",
+            std::process::id()
+        ));
+        let now = Local::now();
+        let old = record(now - Duration::days(100), FailureType::OK);
+        let unreadable = r#"{"timestamp":"2020-01-01T00:00:00+01:00","new_field":1}"#;
+        write(
+            &path,
+            format!("{}\n{unreadable}\n", serde_json::to_string(&old).unwrap()),
+        )
+        .unwrap();
+        append_to(
+            &path,
+            &record(now, FailureType::OK),
+            Some(Duration::days(90)),
+        )
+        .unwrap();
+        let content = read_to_string(&path).unwrap();
+        assert_eq!(content.lines().next(), Some(unreadable));
+        assert_eq!(load_from(&path).len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn failures_of_the_last_days_are_found() {
+        let now = Local::now();
+        let history = vec![
+            record(now - Duration::days(10), FailureType::TriesExceeded),
+            record(now - Duration::days(3), FailureType::OK),
+            record(now - Duration::days(2), FailureType::ConnectError),
+        ];
+        let since = now - Duration::days(7);
+        assert_eq!(query(history.clone(), since, false).len(), 2);
+        let failures = query(history, since, true);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].outcome, FailureType::ConnectError);
+    }
+}
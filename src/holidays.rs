@@ -0,0 +1,147 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use time::{Date, Duration, Month, Weekday};
+
+/*
+All Dutch public holidays that matter for the roster
+Everything is calculated locally, so no network access is needed
+*/
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum Holiday {
+    Nieuwjaarsdag,
+    GoedeVrijdag,
+    EerstePaasdag,
+    TweedePaasdag,
+    Koningsdag,
+    Bevrijdingsdag,
+    Hemelvaartsdag,
+    EerstePinksterdag,
+    TweedePinksterdag,
+    EersteKerstdag,
+    TweedeKerstdag,
+}
+
+impl Display for Holiday {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Holiday::Nieuwjaarsdag => "Nieuwjaarsdag",
+            Holiday::GoedeVrijdag => "Goede Vrijdag",
+            Holiday::EerstePaasdag => "Eerste Paasdag",
+            Holiday::TweedePaasdag => "Tweede Paasdag",
+            Holiday::Koningsdag => "Koningsdag",
+            Holiday::Bevrijdingsdag => "Bevrijdingsdag",
+            Holiday::Hemelvaartsdag => "Hemelvaartsdag",
+            Holiday::EerstePinksterdag => "Eerste Pinksterdag",
+            Holiday::TweedePinksterdag => "Tweede Pinksterdag",
+            Holiday::EersteKerstdag => "Eerste Kerstdag",
+            Holiday::TweedeKerstdag => "Tweede Kerstdag",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/*
+Calculates the date of easter sunday in the gregorian calendar
+Uses the anonymous gregorian algorithm (Meeus/Jones/Butcher)
+*/
+pub fn easter_sunday(year: i32) -> Date {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+    // The algorithm can only return march or april
+    let month = if month == 3 {
+        Month::March
+    } else {
+        Month::April
+    };
+    Date::from_calendar_date(year, month, day as u8).expect("Easter is always a valid date")
+}
+
+// Koningsdag moves to saturday if the 27th of april falls on a sunday
+fn koningsdag(year: i32) -> Date {
+    let date = Date::from_calendar_date(year, Month::April, 27).expect("27 april is always valid");
+    match date.weekday() {
+        Weekday::Sunday => date - Duration::days(1),
+        _ => date,
+    }
+}
+
+// Returns all holidays of a year, sorted by date
+pub fn holidays_in_year(year: i32) -> Vec<(Date, Holiday)> {
+    let fixed_date = |month: Month, day: u8| {
+        Date::from_calendar_date(year, month, day).expect("Fixed holiday is always valid")
+    };
+    let easter = easter_sunday(year);
+    let mut holidays = vec![
+        (fixed_date(Month::January, 1), Holiday::Nieuwjaarsdag),
+        (easter - Duration::days(2), Holiday::GoedeVrijdag),
+        (easter, Holiday::EerstePaasdag),
+        (easter + Duration::days(1), Holiday::TweedePaasdag),
+        (koningsdag(year), Holiday::Koningsdag),
+        (fixed_date(Month::May, 5), Holiday::Bevrijdingsdag),
+        (easter + Duration::days(39), Holiday::Hemelvaartsdag),
+        (easter + Duration::days(49), Holiday::EerstePinksterdag),
+        (easter + Duration::days(50), Holiday::TweedePinksterdag),
+        (fixed_date(Month::December, 25), Holiday::EersteKerstdag),
+        (fixed_date(Month::December, 26), Holiday::TweedeKerstdag),
+    ];
+    holidays.sort_by_key(|holiday| holiday.0);
+    holidays
+}
+
+// Returns the holiday on the given date, if there is one
+pub fn holiday_on(date: Date) -> Option<Holiday> {
+    holidays_in_year(date.year())
+        .into_iter()
+        .find(|holiday| holiday.0 == date)
+        .map(|holiday| holiday.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn easter_dates() {
+        assert_eq!(
+            easter_sunday(2024),
+            Date::from_calendar_date(2024, Month::March, 31).unwrap()
+        );
+        assert_eq!(
+            easter_sunday(2025),
+            Date::from_calendar_date(2025, Month::April, 20).unwrap()
+        );
+        assert_eq!(
+            easter_sunday(2026),
+            Date::from_calendar_date(2026, Month::April, 5).unwrap()
+        );
+    }
+
+    #[test]
+    fn koningsdag_on_sunday_moves_to_saturday() {
+        let date = Date::from_calendar_date(2025, Month::April, 26).unwrap();
+        assert_eq!(holiday_on(date), Some(Holiday::Koningsdag));
+        let date = Date::from_calendar_date(2025, Month::April, 27).unwrap();
+        assert_eq!(holiday_on(date), None);
+    }
+
+    #[test]
+    fn easter_derived_holidays() {
+        let pinksteren = Date::from_calendar_date(2025, Month::June, 9).unwrap();
+        assert_eq!(holiday_on(pinksteren), Some(Holiday::TweedePinksterdag));
+        let hemelvaart = Date::from_calendar_date(2025, Month::May, 29).unwrap();
+        assert_eq!(holiday_on(hemelvaart), Some(Holiday::Hemelvaartsdag));
+    }
+}
@@ -0,0 +1,439 @@
+use std::time::Duration;
+
+use reqwest::Client;
+use scraper::{ElementRef, Html, Selector};
+use time::{Date, Month};
+use url::Url;
+
+use crate::{
+    FALLBACK_URL, FailureType, GenResult, MAIN_URL, SignInFailure,
+    email::DATE_DESCRIPTION,
+    errors::{WebcomError, check_if_webcom_unavailable, check_sign_in_result},
+    messages::{
+        ACKNOWLEDGE_BUTTON_ID, MESSAGE_TABLE_ID, MESSAGES_PATH, WebcomMessage, parse_message_rows,
+    },
+    parsing::parse_user_name,
+    webcom_client::{CalendarDay, ShiftRows, WebcomClient, custom_webcom_url},
+};
+
+const USERNAME_FIELD_ID: &str = "ctl00_cntMainBody_lgnView_lgnLogin_UserName";
+const PASSWORD_FIELD_ID: &str = "ctl00_cntMainBody_lgnView_lgnLogin_Password";
+const LOGIN_BUTTON_ID: &str = "ctl00_cntMainBody_lgnView_lgnLogin_LoginButton";
+// A webcom which accepts the connection and then hangs must not stop the run, the timeout is retried like a page which did not load
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+// The URL to post a form to, and the name and value of every field
+type FilledForm = (Url, Vec<(String, String)>);
+
+// The last page which has been loaded, the HTML is parsed again when needed
+struct Page {
+    url: Url,
+    html: String,
+}
+
+/*
+Talks to webcom directly over HTTP, so no browser is needed
+Signing in is done by posting the ASP.NET login form back, including the __VIEWSTATE and __EVENTVALIDATION fields
+The session is kept in the cookie jar of the client
+*/
+pub struct HttpClient {
+    client: Client,
+    page: Option<Page>,
+    // Used instead of the real webcom, no fallback URLs are used then
+    custom_url: Option<String>,
+}
+
+impl HttpClient {
+    pub fn new() -> GenResult<Self> {
+        Self::with_url(custom_webcom_url())
+    }
+
+    pub fn with_url(custom_url: Option<String>) -> GenResult<Self> {
+        Ok(Self {
+            client: create_client()?,
+            page: None,
+            custom_url,
+        })
+    }
+
+    fn page(&self) -> GenResult<&Page> {
+        self.page
+            .as_ref()
+            .ok_or(WebcomError::scraping("Nog geen webcom pagina geladen"))
+    }
+
+    async fn get(&mut self, url: Url) -> GenResult<()> {
+        debug!("Loading {url}");
+        let response = self.client.get(url).send().await?.error_for_status()?;
+        let url = response.url().clone();
+        let html = response.text().await?;
+        self.page = Some(Page { url, html });
+        Ok(())
+    }
+
+    // Loads a page of webcom, if webcom sends the login page instead, the session has expired
+    async fn get_signed_in(&mut self, path: &str) -> GenResult<&Page> {
+        let url = self.page()?.url.join(path)?;
+        self.get(url).await?;
+        let page = self.page()?;
+        if find_login_form(&page.html, &page.url, "", "").is_ok() {
+            return Err(WebcomError::scraping("Niet meer ingelogd bij webcom"));
+        }
+        if page.url.path() == MESSAGES_PATH {
+            return Err(WebcomError::scraping("Webcom laat eerst berichten zien"));
+        }
+        Ok(page)
+    }
+
+    // Posts a form back to webcom and keeps the page it responds with
+    async fn post(&mut self, action: Url, fields: &[(String, String)]) -> GenResult<&Page> {
+        debug!("Posting form to {action}");
+        let response = self
+            .client
+            .post(action)
+            .form(fields)
+            .send()
+            .await?
+            .error_for_status()?;
+        let url = response.url().clone();
+        let html = response.text().await?;
+        self.page = Some(Page { url, html });
+        self.page()
+    }
+}
+
+impl WebcomClient for HttpClient {
+    async fn open(&mut self, retry_count: usize) -> GenResult<()> {
+        // A new client means a clean cookie jar
+        self.client = create_client()?;
+        self.page = None;
+        let main_url = self
+            .custom_url
+            .clone()
+            .unwrap_or(format!("https://{MAIN_URL}"));
+        info!("Loading site: {}..", main_url);
+        if let Err(error) = self.get(Url::parse(&main_url)?).await {
+            if self.custom_url.is_some() {
+                error!("Failed loading {main_url}: {error}");
+                return Err(FailureType::ConnectError.into());
+            }
+            let fallback_url = FALLBACK_URL[retry_count % FALLBACK_URL.len()];
+            error!("Failed loading {main_url}: {error}. Going to fallback {fallback_url}");
+            self.get(Url::parse(fallback_url)?)
+                .await
+                .map_err(|_| FailureType::ConnectError)?;
+        }
+        Ok(())
+    }
+
+    async fn login(&mut self, user: &str, pass: &str) -> GenResult<()> {
+        let page = self.page()?;
+        let (action, fields) = find_login_form(&page.html, &page.url, user, pass)?;
+        let page = self.post(action, &fields).await?;
+        let on_messages_page = page.url.path() == MESSAGES_PATH;
+        let document = Html::parse_document(&page.html);
+        check_sign_in_result(
+            on_messages_page,
+            select_text(&document, "h3"),
+            select_text(&document, "#ctl00_lblMessage"),
+        )
+    }
+
+    async fn current_user_name(&mut self) -> GenResult<Option<String>> {
+        let page = self.page()?;
+        if page.url.path() == MESSAGES_PATH {
+            info!("Got redirected to message, wont try to get name");
+            return Ok(None);
+        }
+        let name_text = select_text(&Html::parse_document(&page.html), "h3")
+            .ok_or(WebcomError::scraping("Geen naam gevonden"))?;
+        Ok(Some(parse_user_name(&name_text)?))
+    }
+
+    async fn pending_messages(&mut self) -> GenResult<Vec<WebcomMessage>> {
+        let page = self.page()?;
+        if page.url.path() != MESSAGES_PATH {
+            return Ok(vec![]);
+        }
+        parse_messages(&page.html)
+    }
+
+    async fn acknowledge_messages(&mut self) -> GenResult<()> {
+        let page = self.page()?;
+        if page.url.path() != MESSAGES_PATH {
+            return Ok(());
+        }
+        let page = match find_form(&page.html, &page.url, ACKNOWLEDGE_BUTTON_ID, &[])? {
+            Some((action, fields)) => {
+                info!("Marking webcom messages as read");
+                self.post(action, &fields).await?
+            }
+            None => {
+                warn!("No button to mark the webcom messages as read, opening the home page");
+                let url = page.url.join("/WebComm/home.aspx")?;
+                self.get(url).await?;
+                self.page()?
+            }
+        };
+        if page.url.path() == MESSAGES_PATH {
+            return Err(WebcomError::scraping(
+                "Berichten pagina kon niet verlaten worden",
+            ));
+        }
+        Ok(())
+    }
+
+    async fn fetch_month(&mut self, year: i32, month: Month) -> GenResult<Vec<CalendarDay>> {
+        let page = self
+            .get_signed_in(&format!("/WebComm/roster.aspx?{}-{}-01", year, month as u8))
+            .await?;
+        Ok(parse_calendar_days(&page.html))
+    }
+
+    async fn fetch_shift_detail(&mut self, date: Date) -> GenResult<ShiftRows> {
+        let formatted_date = date.format(DATE_DESCRIPTION)?;
+        let page = self
+            .get_signed_in(&format!("/WebComm/shift.aspx?{}", formatted_date))
+            .await?;
+        parse_shift_rows(&page.html)
+    }
+
+    async fn quit(self) -> GenResult<()> {
+        Ok(())
+    }
+}
+
+fn create_client() -> GenResult<Client> {
+    Ok(Client::builder()
+        .cookie_store(true)
+        .user_agent(concat!("webcom_ical/", env!("CARGO_PKG_VERSION")))
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(REQUEST_TIMEOUT)
+        .build()?)
+}
+
+fn selector(selectors: &str) -> Selector {
+    Selector::parse(selectors).expect("Invalid selector")
+}
+
+fn element_text(element: ElementRef) -> String {
+    element.text().collect::<String>().trim().to_owned()
+}
+
+fn select_text(document: &Html, selectors: &str) -> Option<String> {
+    document
+        .select(&selector(selectors))
+        .next()
+        .map(element_text)
+}
+
+/*
+Finds the login form and fills it in
+Returns the URL to post it to, and all fields of the form
+*/
+fn find_login_form(html: &str, page_url: &Url, user: &str, pass: &str) -> GenResult<FilledForm> {
+    let values = [(USERNAME_FIELD_ID, user), (PASSWORD_FIELD_ID, pass)];
+    match find_form(html, page_url, LOGIN_BUTTON_ID, &values)? {
+        Some(form) => Ok(form),
+        None => Err(match check_if_webcom_unavailable(select_text(
+            &Html::parse_document(html),
+            "h3",
+        )) {
+            true => FailureType::SignInFailed(SignInFailure::WebcomDown),
+            false => FailureType::Other("Geen inlogveld gevonden".to_owned()),
+        }
+        .into()),
+    }
+}
+
+/*
+Finds the form containing the button with button_id, and fills it in as if that button was pressed
+The values are used for the inputs with the same id, every other input keeps the value it has on the page
+Every input of the form is posted back, as ASP.NET needs the hidden state fields
+Returns None if the button is not on the page
+*/
+fn find_form(
+    html: &str,
+    page_url: &Url,
+    button_id: &str,
+    values: &[(&str, &str)],
+) -> GenResult<Option<FilledForm>> {
+    let document = Html::parse_document(html);
+    let Some(form) = document.select(&selector("form")).find(|form| {
+        form.select(&selector(&format!("#{button_id}")))
+            .next()
+            .is_some()
+    }) else {
+        return Ok(None);
+    };
+    let action = page_url.join(form.value().attr("action").unwrap_or_default())?;
+    let mut fields = vec![];
+    for input in form.select(&selector("input")) {
+        let element = input.value();
+        let Some(name) = element.attr("name") else {
+            continue;
+        };
+        let id = element.attr("id").unwrap_or_default();
+        let value = match values.iter().find(|(value_id, _)| *value_id == id) {
+            Some((_, value)) => value,
+            None => element.attr("value").unwrap_or_default(),
+        };
+        // Only the button which is pressed is posted back
+        let is_button = matches!(
+            element.attr("type").unwrap_or_default(),
+            "submit" | "button" | "image"
+        );
+        if is_button && id != button_id {
+            continue;
+        }
+        let is_checkbox = matches!(
+            element.attr("type").unwrap_or_default(),
+            "checkbox" | "radio"
+        );
+        if is_checkbox && element.attr("checked").is_none() {
+            continue;
+        }
+        fields.push((name.to_owned(), value.to_owned()));
+    }
+    Ok(Some((action, fields)))
+}
+
+fn parse_messages(html: &str) -> GenResult<Vec<WebcomMessage>> {
+    let document = Html::parse_document(html);
+    let table = document
+        .select(&selector(&format!("#{MESSAGE_TABLE_ID}")))
+        .next()
+        .ok_or(WebcomError::scraping("Geen berichten tabel gevonden"))?;
+    Ok(parse_message_rows(
+        table
+            .select(&selector("tr"))
+            .map(|row| row.select(&selector("td")).map(element_text).collect())
+            .collect(),
+    ))
+}
+
+/*
+Finds every calDay in the roster
+The browser moves the title of a day to data-original-title when the tooltips are created,
+in the raw HTML it can still be in the title
+*/
+fn parse_calendar_days(html: &str) -> Vec<CalendarDay> {
+    let document = Html::parse_document(html);
+    document
+        .select(&selector(".calDay"))
+        .map(|day| CalendarDay {
+            tooltip: day
+                .value()
+                .attr("data-original-title")
+                .or(day.value().attr("title"))
+                .map(str::to_owned),
+            day_text: day
+                .select(&selector("strong"))
+                .next()
+                .map(element_text)
+                .unwrap_or_default(),
+        })
+        .collect()
+}
+
+fn parse_shift_rows(html: &str) -> GenResult<ShiftRows> {
+    let document = Html::parse_document(html);
+    let body = document
+        .select(&selector("tbody"))
+        .next()
+        .ok_or(WebcomError::scraping("Geen ritten tabel gevonden"))?;
+    Ok(body
+        .select(&selector("tr"))
+        .map(|row| row.select(&selector("td")).map(element_text).collect())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOGIN_PAGE: &str = r#"<html><body>
+        <form method="post" action="./default.aspx" id="aspnetForm">
+            <input type="hidden" name="__VIEWSTATE" id="__VIEWSTATE" value="viewstate" />
+            <input type="hidden" name="__EVENTVALIDATION" id="__EVENTVALIDATION" value="validation" />
+            <input name="ctl00$cntMainBody$lgnView$lgnLogin$UserName" type="text" id="ctl00_cntMainBody_lgnView_lgnLogin_UserName" />
+            <input name="ctl00$cntMainBody$lgnView$lgnLogin$Password" type="password" id="ctl00_cntMainBody_lgnView_lgnLogin_Password" />
+            <input type="checkbox" name="ctl00$cntMainBody$lgnView$lgnLogin$RememberMe" />
+            <input type="submit" name="ctl00$cntMainBody$lgnView$lgnLogin$LoginButton" value="Aanmelden" id="ctl00_cntMainBody_lgnView_lgnLogin_LoginButton" />
+            <input type="submit" name="ctl00$other" value="Anders" />
+        </form>
+    </body></html>"#;
+
+    #[test]
+    fn login_form_is_filled_in() {
+        let page_url = Url::parse("https://webcom.test/WebComm/default.aspx").unwrap();
+        let (action, fields) = find_login_form(LOGIN_PAGE, &page_url, "12345", "geheim").unwrap();
+        assert_eq!(action.as_str(), "https://webcom.test/WebComm/default.aspx");
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(field_name, _)| field_name == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(field("__VIEWSTATE"), Some("viewstate"));
+        assert_eq!(field("__EVENTVALIDATION"), Some("validation"));
+        assert_eq!(
+            field("ctl00$cntMainBody$lgnView$lgnLogin$UserName"),
+            Some("12345")
+        );
+        assert_eq!(
+            field("ctl00$cntMainBody$lgnView$lgnLogin$Password"),
+            Some("geheim")
+        );
+        assert_eq!(
+            field("ctl00$cntMainBody$lgnView$lgnLogin$LoginButton"),
+            Some("Aanmelden")
+        );
+        assert_eq!(field("ctl00$cntMainBody$lgnView$lgnLogin$RememberMe"), None);
+        assert_eq!(field("ctl00$other"), None);
+    }
+
+    #[test]
+    fn outage_page_is_webcom_down() {
+        let page_url = Url::parse("https://webcom.test/").unwrap();
+        let html = "<html><body><h3>De servertoepassing is niet beschikbaar.</h3></body></html>";
+        let error = find_login_form(html, &page_url, "", "").unwrap_err();
+        assert_eq!(
+            error.failure(),
+            Some(&FailureType::SignInFailed(SignInFailure::WebcomDown))
+        );
+    }
+
+    #[test]
+    fn calendar_days_are_parsed() {
+        let html = r#"<table><tr>
+            <td class="calDay" title="Dienst: 1234"><strong>3 ma</strong></td>
+            <td class="calDay" data-original-title=""><strong>4</strong></td>
+        </tr></table>"#;
+        assert_eq!(
+            parse_calendar_days(html),
+            vec![
+                CalendarDay {
+                    tooltip: Some("Dienst: 1234".to_owned()),
+                    day_text: "3 ma".to_owned()
+                },
+                CalendarDay {
+                    tooltip: Some(String::new()),
+                    day_text: "4".to_owned()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn shift_rows_are_parsed() {
+        let html = "<table><tbody>
+            <tr><td>Rit</td><td>08:00</td><td>A</td><td>10:00</td></tr>
+            <tr><td>Rit</td><td>12:00</td><td>B</td><td>14:00</td></tr>
+        </tbody></table>";
+        let rows = parse_shift_rows(html).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1], vec!["Rit", "12:00", "B", "14:00"]);
+    }
+}
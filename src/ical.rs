@@ -6,10 +6,18 @@ use std::{
 };
 
 use crate::{
-    FailureType, GenResult, Shift, ShiftState, create_ical_filename, create_shift_link,
+    BASE_DIRECTORY, FailureType, GenResult, Shift, ShiftState,
+    absence::Absence,
+    create_ical_filename, create_shift_link,
+    feeds::{CalendarFeed, ShiftChange, free_busy_title},
+    schedule::kuma_heartbeat_interval,
     set_get_name,
+    team::{TeamPrivacy, TeamShifts},
+};
+use crate::{
+    email::{DATE_DESCRIPTION, TIME_DESCRIPTION},
+    errors::{OptionResult, ResultLog, WebcomError},
 };
-use crate::{email::TIME_DESCRIPTION, errors::ResultLog};
 use chrono::{Datelike, Local, Months, NaiveDate, NaiveDateTime, NaiveTime};
 use dotenvy::var;
 use icalendar::{
@@ -33,14 +41,14 @@ impl ToNaive for time::Date {
 // UPDATE THIS WHENEVER ANYTHING CHANGES IN THE ICAL
 // Add B if it modifies of removes an already existing value
 // Add W if it is wanted to resend the welcome mail
-pub const CALENDAR_VERSION: &str = "4";
+pub const CALENDAR_VERSION: &str = "8";
 
 const PREVIOUS_EXECUTION_DATE_PATH: &str = "./kuma/previous_execution_date";
 pub const NON_RELEVANT_EVENTS_PATH: &str = "./kuma/non_relevant_events";
 pub const RELEVANT_EVENTS_PATH: &str = "./kuma/relevant_events";
 
 #[derive(Debug, Error, Clone, PartialEq)]
-enum CalendarVersionError {
+pub enum CalendarVersionError {
     #[error("Calendar version changed with a breaking change")]
     BreakingChange,
     #[error("Calendar version has changed, and welcome mail is requested")]
@@ -58,11 +66,11 @@ pub fn load_ical_file(path: &Path) -> GenResult<Calendar> {
                 match version_type {
                     'B' => {
                         warn!("Breaking change");
-                        return Err(Box::new(CalendarVersionError::BreakingChange));
+                        return Err(CalendarVersionError::BreakingChange.into());
                     }
                     'W' => {
                         warn!("Welcome change");
-                        return Err(Box::new(CalendarVersionError::WelcomeChange));
+                        return Err(CalendarVersionError::WelcomeChange.into());
                     }
                     _ => {
                         info!("Non beaking change");
@@ -159,11 +167,16 @@ fn is_partial_calendar_regeneration_needed() -> GenResult<Option<bool>> {
     }
 }
 
+/*
+Reads the shifts from the events of a calendar made before version 7, which contained the whole shift in X-BUSSIE-METADATA
+Only used when there are no stored shifts yet, newer calendars only contain X-SHIFT-ID and give no shifts
+*/
 fn event_to_shift(events: Vec<Event>) -> Vec<Shift> {
     let mut previous_shift_map: HashMap<i64, Shift> = HashMap::new();
     for event in events {
         if let Some(shift_string) = event.property_value("X-BUSSIE-METADATA") {
-            if let Ok(shift) = serde_json::from_str::<Shift>(shift_string) {
+            if let Ok(mut shift) = serde_json::from_str::<Shift>(shift_string) {
+                shift.magic_number = shift.calculate_magic_number();
                 // let mut shift = shift;
                 // All shifts are marked to be deleted. As if they are not marked that later on we know they really should be deleted
                 // shift.state = ShiftState::Deleted;
@@ -197,6 +210,51 @@ pub fn save_partial_shift_files(shifts: &Vec<Shift>) -> GenResult<()> {
     Ok(())
 }
 
+// Load all shifts saved to disk by the last run, sorted by date
+pub fn load_stored_shifts() -> GenResult<Vec<Shift>> {
+    load_stored_shifts_from(Path::new(BASE_DIRECTORY))
+}
+
+// Load the stored shifts of another webcom ical instance, directory is the kuma directory of that instance
+pub fn load_stored_shifts_from(directory: &Path) -> GenResult<Vec<Shift>> {
+    let mut shifts: Vec<Shift> = vec![];
+    for path in [NON_RELEVANT_EVENTS_PATH, RELEVANT_EVENTS_PATH] {
+        shifts.append(&mut read_shift_file(
+            &directory.join(Path::new(path).file_name().result("calendar file name")?),
+        )?);
+    }
+    shifts.sort_by_key(|shift| (shift.date, shift.start));
+    Ok(shifts)
+}
+
+// Shifts stored by an older version can have an id from a different hash, so the id is calculated again
+fn read_shift_file(path: &Path) -> GenResult<Vec<Shift>> {
+    let mut shifts: Vec<Shift> = serde_json::from_str(&read_to_string(path)?)?;
+    for shift in shifts.iter_mut() {
+        shift.magic_number = shift.calculate_magic_number();
+    }
+    Ok(shifts)
+}
+
+/*
+Adds shifts to the stored non-relevant shifts
+Stored shifts on the same day as one of the new shifts are replaced
+*/
+pub fn merge_non_relevant_shifts(shifts: &[Shift]) -> GenResult<()> {
+    let mut stored_shifts: Vec<Shift> = match read_to_string(NON_RELEVANT_EVENTS_PATH) {
+        Ok(shifts_str) => serde_json::from_str(&shifts_str)?,
+        Err(_) => vec![],
+    };
+    stored_shifts.retain(|stored| !shifts.iter().any(|shift| shift.date == stored.date));
+    stored_shifts.extend_from_slice(shifts);
+    stored_shifts.sort_by_key(|shift| (shift.date, shift.start));
+    write(
+        NON_RELEVANT_EVENTS_PATH,
+        serde_json::to_string_pretty(&stored_shifts)?,
+    )?;
+    Ok(())
+}
+
 #[derive(Debug, Default)]
 pub struct PreviousShiftInformation {
     pub previous_relevant_shifts: Vec<Shift>,
@@ -221,64 +279,65 @@ pub fn get_previous_shifts() -> GenResult<Option<PreviousShiftInformation>> {
         if !main_ical_path.exists() {
             return Ok(None);
         }
+        // The calendar is still loaded to check if its version requires starting over
         let main_calendar = match load_ical_file(&main_ical_path) {
             Ok(calendar) => calendar,
-            Err(err) => {
-                return match err.downcast_ref::<CalendarVersionError>() {
-                    Some(ver_err) if ver_err == &CalendarVersionError::BreakingChange => Ok(None),
-                    Some(ver_err) if ver_err == &CalendarVersionError::WelcomeChange => {
-                        info!("Removing existing calendar file");
-                        _ = fs::remove_file(main_ical_path);
-                        Ok(None)
-                    }
-                    _ => Err(err),
-                };
+            Err(WebcomError::CalendarVersion(CalendarVersionError::BreakingChange)) => {
+                return Ok(None);
+            }
+            Err(WebcomError::CalendarVersion(CalendarVersionError::WelcomeChange)) => {
+                info!("Removing existing calendar file");
+                _ = fs::remove_file(main_ical_path);
+                return Ok(None);
+            }
+            Err(err) => return Err(err),
+        };
+        // The stored shifts are split again, as shifts can have become non-relevant since the last run
+        let stored_shifts = match relevant_events_exist && non_relevant_events_exist {
+            true => load_stored_shifts()?,
+            false => {
+                info!("No stored shifts, reading them from the calendar");
+                event_to_shift(get_calendar_events(main_calendar))
             }
         };
-        let calendar_events = get_calendar_events(main_calendar);
-        let calendar_shifts = event_to_shift(calendar_events);
         let (previous_relevant_shifts, previous_non_relevant_shifts) =
-            split_relevant_shifts(calendar_shifts);
-
-        let previous_relevant_shifts: Vec<Shift> = previous_relevant_shifts
-            .into_iter()
-            .map(|mut shift| {
-                shift.state = ShiftState::Deleted;
-                shift
-            })
-            .collect();
+            split_relevant_shifts(stored_shifts);
         debug!(
             "Got {} relevant and {} non-relevant events",
             previous_relevant_shifts.len(),
             previous_non_relevant_shifts.len()
         );
         Ok(Some(PreviousShiftInformation {
-            previous_relevant_shifts,
+            previous_relevant_shifts: mark_deleted(previous_relevant_shifts),
             previous_non_relevant_shifts,
         }))
     } else {
         info!("Calendar regeneration NOT needed");
-        let relevant_shift_str = read_to_string(RELEVANT_EVENTS_PATH)?;
-        let non_relevant_shifts_str = read_to_string(NON_RELEVANT_EVENTS_PATH)?;
-        let previous_relevant_shifts: Vec<Shift> = serde_json::from_str(&relevant_shift_str)?;
-        // All relevant shifts MUST FIRST BE MARKED AS DELETED for deleted shift detection to work
-        let previous_relevant_shifts = previous_relevant_shifts
-            .into_iter()
-            .map(|mut shift| {
-                shift.state = ShiftState::Deleted;
-                shift
-            })
-            .collect();
-        let previous_non_relevant_shifts: Vec<Shift> =
-            serde_json::from_str(&non_relevant_shifts_str)?;
+        let previous_relevant_shifts = read_shift_file(Path::new(RELEVANT_EVENTS_PATH))?;
+        let previous_non_relevant_shifts = read_shift_file(Path::new(NON_RELEVANT_EVENTS_PATH))?;
         Ok(Some(PreviousShiftInformation {
-            previous_relevant_shifts,
+            previous_relevant_shifts: mark_deleted(previous_relevant_shifts),
             previous_non_relevant_shifts,
         }))
     }
 }
 
-fn create_event(shift: &Shift, metadata: Option<&&Shift>) -> Event {
+// All relevant shifts MUST FIRST BE MARKED AS DELETED for deleted shift detection to work
+fn mark_deleted(shifts: Vec<Shift>) -> Vec<Shift> {
+    shifts
+        .into_iter()
+        .map(|mut shift| {
+            shift.state = ShiftState::Deleted;
+            shift
+        })
+        .collect()
+}
+
+/*
+X-SHIFT-ID is the same for every part of a split shift, so the parts can be linked to the stored shift
+The shift itself is stored in the kuma directory, not in the calendar
+*/
+fn create_event(shift: &Shift) -> Event {
     let shift_link = create_shift_link(shift, true).unwrap_or("ERROR".to_owned());
     let cut_off_end_time = if let Some(end_time) = shift.original_end_time {
         format!(
@@ -288,13 +347,25 @@ fn create_event(shift: &Shift, metadata: Option<&&Shift>) -> Event {
     } else {
         String::new()
     };
-    Event::new()
-        .summary(&format!("Dienst - {}{cut_off_end_time}", shift.number))
+    let holiday = shift.holiday();
+    let (holiday_prefix, holiday_description) = match holiday {
+        Some(holiday) => (format!("{holiday} • "), format!("\nFeestdag • {holiday}")),
+        None => (String::new(), String::new()),
+    };
+    let mut event = Event::new();
+    if let Some(holiday) = holiday {
+        event.add_property("CATEGORIES", format!("Feestdag,{holiday}"));
+    }
+    event
+        .summary(&format!(
+            "{holiday_prefix}Dienst - {}{cut_off_end_time}",
+            shift.number
+        ))
         .description(&format!(
             "Dienstsoort • {}
 Duur • {} uur {} minuten
 Omschrijving • {}
-Shift sheet • {}",
+Shift sheet • {}{holiday_description}",
             shift.kind,
             shift.duration.whole_hours(),
             shift.duration.whole_minutes() % 60,
@@ -302,37 +373,43 @@ Shift sheet • {}",
             shift_link
         ))
         .location(&shift.location)
-        .append_property(icalendar::Property::new(
-            "X-BUSSIE-METADATA",
-            &serde_json::to_string(metadata.unwrap_or(&shift)).unwrap_or_default(),
-        ))
+        .add_property("X-SHIFT-ID", shift.magic_number.to_string())
         .starts(create_dateperhapstime(shift.date, shift.start))
         .ends(create_dateperhapstime(shift.end_date, shift.end))
         .done()
 }
 
+// Absences are all-day events which do not make the user busy
+fn create_absence_event(absence: &Absence) -> Option<Event> {
+    Some(
+        Event::new()
+            .summary(absence.status.summary())
+            .description(&absence.description)
+            .all_day(absence.date.to_naive()?)
+            .add_property("CATEGORIES", "Afwezigheid")
+            .add_property("TRANSP", "TRANSPARENT")
+            .done(),
+    )
+}
+
 /*
 Creates the ICAL file to add to the calendar
 Needs previous exit code so it can add it to the calendar
 Will later be replaced with current exit code if its different
+Absences are added as all-day events, pass an empty slice if they are in their own calendar
 */
 pub fn create_ical(
     shifts: &Vec<Shift>,
-    metadata: &Vec<Shift>,
+    absences: &[Absence],
     previous_exit_code: &FailureType,
 ) -> String {
-    let metadata_shifts_hashmap: HashMap<i64, &Shift> =
-        metadata.into_iter().map(|x| (x.magic_number, x)).collect();
     let name = set_get_name(None);
     let admin_email = var("MAIL_ERROR_TO").unwrap_or_default();
     // get the current systemtime as a unix timestamp
     let current_timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap_or(Duration::from_secs(0));
-    let heartbeat_interval: i32 = var("KUMA_HEARTBEAT_INTERVAL")
-        .unwrap_or("0".to_owned())
-        .parse()
-        .unwrap_or(0);
+    let heartbeat_interval = kuma_heartbeat_interval().unwrap_or(0);
     info!("Creating calendar file...");
     let mut calendar = Calendar::new()
         .name(&format!("Hermes rooster - {}", name))
@@ -357,12 +434,170 @@ pub fn create_ical(
         .timezone("Europe/Amsterdam")
         .done();
     for shift in shifts {
-        let metadata_shift = metadata_shifts_hashmap.get(&shift.magic_number);
-        calendar.push(create_event(&shift, metadata_shift));
+        calendar.push(create_event(shift));
+    }
+    for absence in absences {
+        if let Some(event) = create_absence_event(absence) {
+            calendar.push(event);
+        }
     }
     String::from(calendar.to_string())
 }
 
+// The unpaid break of a broken shift, from when the first part ends until the second part starts
+fn create_break_event(shift: &Shift, period: (Time, Time)) -> Event {
+    let end_date = match period.1 < period.0 {
+        true => shift.date + time::Duration::days(1),
+        false => shift.date,
+    };
+    Event::new()
+        .summary(&format!("Pauze - {}", shift.number))
+        .description(&format!(
+            "Onderbreking van gebroken dienst {}",
+            shift.number
+        ))
+        .location(&shift.location)
+        .add_property("TRANSP", "TRANSPARENT")
+        .starts(create_dateperhapstime(shift.date, period.0))
+        .ends(create_dateperhapstime(end_date, period.1))
+        .done()
+}
+
+// A change to the roster, at the time of the shift which changed
+fn create_change_event(change: &ShiftChange) -> Event {
+    let shift = &change.shift;
+    let change_text = match change.state {
+        ShiftState::New => "Nieuw",
+        ShiftState::Deleted => "Verwijderd",
+        _ => "Gewijzigd",
+    };
+    Event::new()
+        .summary(&format!("{change_text} • Dienst - {}", shift.number))
+        .description(&format!(
+            "{change_text} sinds {}
+Tijd • {} - {}
+Dienstsoort • {}",
+            change.detected.format(DATE_DESCRIPTION).unwrap_or_default(),
+            shift.start.format(TIME_DESCRIPTION).unwrap_or_default(),
+            shift.end.format(TIME_DESCRIPTION).unwrap_or_default(),
+            shift.kind
+        ))
+        .location(&shift.location)
+        .add_property("CATEGORIES", change_text)
+        .add_property("TRANSP", "TRANSPARENT")
+        .starts(create_dateperhapstime(shift.date, shift.start))
+        .ends(create_dateperhapstime(shift.end_date, shift.end))
+        .done()
+}
+
+// The empty calendar of a feed, every feed has its own name so they can be told apart in calendar apps
+fn create_feed_calendar(feed: CalendarFeed) -> Calendar {
+    let name = set_get_name(None);
+    Calendar::new()
+        .name(&feed.calendar_name(&name))
+        .append_property(("X-USER-NAME", name.as_str()))
+        .append_property(("X-CAL-VERSION", CALENDAR_VERSION))
+        .append_property(("METHOD", "PUBLISH"))
+        .timezone("Europe/Amsterdam")
+        .done()
+}
+
+// Only the shifts, split just like in the main calendar
+pub fn create_shifts_feed(split_shifts: &[Shift]) -> String {
+    let mut calendar = create_feed_calendar(CalendarFeed::Shifts);
+    for shift in split_shifts {
+        calendar.push(create_event(shift));
+    }
+    calendar.to_string()
+}
+
+pub fn create_breaks_feed(all_shifts: &[Shift]) -> String {
+    let mut calendar = create_feed_calendar(CalendarFeed::Breaks);
+    for shift in all_shifts {
+        for period in shift.broken_period.iter().flatten() {
+            calendar.push(create_break_event(shift, *period));
+        }
+    }
+    calendar.to_string()
+}
+
+pub fn create_absences_feed(absences: &[Absence]) -> String {
+    let mut calendar = create_feed_calendar(CalendarFeed::Absences);
+    for absence in absences {
+        if let Some(event) = create_absence_event(absence) {
+            calendar.push(event);
+        }
+    }
+    calendar.to_string()
+}
+
+pub fn create_changes_feed(changes: &[ShiftChange]) -> String {
+    let mut calendar = create_feed_calendar(CalendarFeed::Changes);
+    for change in changes {
+        calendar.push(create_change_event(change));
+    }
+    calendar.to_string()
+}
+
+// A shift in the team calendar, busy members only show when they are working
+fn create_team_event(member: &TeamShifts, shift: &Shift) -> Event {
+    let mut event = Event::new();
+    match member.privacy {
+        TeamPrivacy::Details => event
+            .summary(&format!("{} • Dienst - {}", member.name, shift.number))
+            .description(&format!(
+                "Dienstsoort • {}
+Duur • {} uur {} minuten
+Omschrijving • {}",
+                shift.kind,
+                shift.duration.whole_hours(),
+                shift.duration.whole_minutes() % 60,
+                shift.description
+            ))
+            .location(&shift.location),
+        TeamPrivacy::Busy => event.summary(&format!("{} • Bezet", member.name)),
+    };
+    event
+        .starts(create_dateperhapstime(shift.date, shift.start))
+        .ends(create_dateperhapstime(shift.end_date, shift.end))
+        .done()
+}
+
+// The shifts of all members of a team in a single calendar
+pub fn create_team_ical(team_name: &str, members: &[TeamShifts]) -> String {
+    let mut calendar = Calendar::new()
+        .name(&format!("Hermes team - {team_name}"))
+        .append_property(("X-CAL-VERSION", CALENDAR_VERSION))
+        .append_property(("METHOD", "PUBLISH"))
+        .timezone("Europe/Amsterdam")
+        .done();
+    for member in members {
+        for shift in &member.shifts {
+            calendar.push(create_team_event(member, shift));
+        }
+    }
+    calendar.to_string()
+}
+
+/*
+Only the start and end of every shift, with the same title for all of them
+No description, location, shift sheet link or shift id, as this feed is meant to be shared
+*/
+pub fn create_free_busy_feed(split_shifts: &[Shift]) -> String {
+    let title = free_busy_title();
+    let mut calendar = create_feed_calendar(CalendarFeed::FreeBusy);
+    for shift in split_shifts {
+        calendar.push(
+            Event::new()
+                .summary(&title)
+                .starts(create_dateperhapstime(shift.date, shift.start))
+                .ends(create_dateperhapstime(shift.end_date, shift.end))
+                .done(),
+        );
+    }
+    calendar.to_string()
+}
+
 /*
 I use the create Time to keep track of dates and time. But the crate used for creating the ICAL file uses chrono to keep time.
 */
@@ -381,3 +616,53 @@ fn create_dateperhapstime(date: Date, time: Time) -> CalendarDateTime {
         tzid: "Europe/Amsterdam".to_string(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Month;
+
+    fn shift() -> Shift {
+        let date = Date::from_calendar_date(2025, Month::June, 2).unwrap();
+        Shift::test_shift(date, "V1")
+    }
+
+    #[test]
+    fn events_only_contain_shift_id() {
+        let shift = shift();
+        let calendar = create_ical(&vec![shift.clone()], &[], &FailureType::default());
+        assert!(calendar.contains(&format!("X-SHIFT-ID:{}", shift.magic_number)));
+        assert!(!calendar.contains("X-BUSSIE-METADATA"));
+        let events = get_calendar_events(read_calendar(&unfold(&calendar)).unwrap().into());
+        assert!(event_to_shift(events).is_empty());
+    }
+
+    #[test]
+    fn shifts_are_read_from_old_calendars() {
+        let shift = shift();
+        let event = Event::new()
+            .summary("Dienst - V1")
+            .append_property(icalendar::Property::new(
+                "X-BUSSIE-METADATA",
+                serde_json::to_string(&shift).unwrap(),
+            ))
+            .done();
+        let calendar = Calendar::new().push(event).done().to_string();
+        let events = get_calendar_events(read_calendar(&unfold(&calendar)).unwrap().into());
+        assert_eq!(event_to_shift(events), vec![shift]);
+    }
+
+    #[test]
+    fn stored_shifts_with_an_old_id_are_unchanged() {
+        let shift = shift();
+        let mut stored_shift = shift.clone();
+        stored_shift.magic_number = 1234;
+        let path = std::env::temp_dir().join(format!("webcom_shifts_{}", std::process::id()));
+        write(&path, serde_json::to_string(&vec![stored_shift]).unwrap()).unwrap();
+        let previous_shifts = mark_deleted(read_shift_file(&path).unwrap());
+        _ = fs::remove_file(&path);
+        let shifts = crate::email::compare_shifts(previous_shifts, vec![shift]);
+        assert_eq!(shifts.len(), 1);
+        assert_eq!(shifts[0].state, ShiftState::Unchanged);
+    }
+}
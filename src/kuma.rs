@@ -9,7 +9,7 @@ use strfmt::strfmt;
 use url::Url;
 
 use crate::errors::OptionResult;
-use crate::{email, set_get_name, GenResult};
+use crate::{email, schedule::kuma_heartbeat_interval, set_get_name, GenResult};
 
 const COLOR_RED: &str = "#a51d2d";
 const COLOR_GREEN: &str = "#26a269";
@@ -54,7 +54,7 @@ async fn create_monitor(
     personeelsnummer: &str,
     notification_id: i32,
 ) -> GenResult<i32> {
-    let heartbeat_interval: i32 = var("KUMA_HEARTBEAT_INTERVAL")?.parse()?;
+    let heartbeat_interval = kuma_heartbeat_interval()?;
     let heartbeat_retry: i32 = var("KUMA_HEARTBEAT_RETRY")?.parse()?;
     let group_id: i32 = get_monitor_type_id(kuma_client, "Webcom Ical", MonitorType::Group, true)
         .await?
@@ -70,7 +70,7 @@ async fn create_monitor(
         ..Default::default()
     };
     let monitor_response = kuma_client.add_monitor(monitor).await?;
-    let monitor_id = monitor_response.common().id().result()?;
+    let monitor_id = monitor_response.common().id().result("kuma monitor id")?;
     info!("Monitor has been created, id: {monitor_id}");
     Ok(monitor_id)
 }
@@ -156,7 +156,7 @@ Webcom Ical weer online
     };
 
     let notification_response = kuma_client.add_notification(notification.clone()).await?;
-    let id = notification_response.id.result()?;
+    let id = notification_response.id.result("kuma notification id")?;
     info!("Created notification with ID {id}");
     Ok((id, true))
 }
@@ -0,0 +1,157 @@
+use std::{
+    io::Write,
+    sync::{LazyLock, RwLock},
+};
+
+use chrono::Local;
+use dotenvy::var;
+use log::{
+    Record,
+    kv::{self, Key, Value, VisitSource},
+};
+use serde::Serialize;
+use serde_json::{Map, json};
+
+use crate::execution::StartReason;
+
+static RUN_CONTEXT: LazyLock<RwLock<RunContext>> =
+    LazyLock::new(|| RwLock::new(RunContext::default()));
+
+// The part of a run which is being done, added to every log record
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Login,
+    FetchMonth,
+    BrokenShifts,
+    Diff,
+    Mail,
+    WriteIcs,
+}
+
+// Everything logged between start_run and end_run shares the same run id
+#[derive(Debug, Clone, Default)]
+struct RunContext {
+    run_id: Option<String>,
+    start_reason: Option<StartReason>,
+    phase: Option<Phase>,
+}
+
+// Starts a new run and returns its id
+pub fn start_run(start_reason: StartReason) -> String {
+    let run_id = format!("{:016x}", rand::random::<u64>());
+    if let Ok(mut context) = RUN_CONTEXT.write() {
+        *context = RunContext {
+            run_id: Some(run_id.clone()),
+            start_reason: Some(start_reason),
+            phase: None,
+        };
+    }
+    run_id
+}
+
+pub fn end_run() {
+    if let Ok(mut context) = RUN_CONTEXT.write() {
+        *context = RunContext::default();
+    }
+}
+
+pub fn set_phase(phase: Phase) {
+    if let Ok(mut context) = RUN_CONTEXT.write() {
+        context.phase = Some(phase);
+    }
+}
+
+// The id of the run in progress, None outside of a run
+pub fn run_id() -> Option<String> {
+    RUN_CONTEXT
+        .read()
+        .ok()
+        .and_then(|context| context.run_id.clone())
+}
+
+// Collects the structured fields of a record, like the function and error of ResultLog
+struct Fields<'a>(&'a mut Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.insert(key.to_string(), json!(value.to_string()));
+        Ok(())
+    }
+}
+
+fn current_context() -> RunContext {
+    RUN_CONTEXT
+        .read()
+        .map(|context| context.clone())
+        .unwrap_or_default()
+}
+
+fn json_record(record: &Record, user: &str, context: &RunContext) -> String {
+    let mut fields = Map::new();
+    fields.insert("timestamp".to_owned(), json!(Local::now().to_rfc3339()));
+    fields.insert("level".to_owned(), json!(record.level().as_str()));
+    fields.insert("target".to_owned(), json!(record.target()));
+    fields.insert("message".to_owned(), json!(record.args().to_string()));
+    fields.insert("user".to_owned(), json!(user));
+    fields.insert("run_id".to_owned(), json!(context.run_id));
+    fields.insert("start_reason".to_owned(), json!(context.start_reason));
+    fields.insert("phase".to_owned(), json!(context.phase));
+    _ = record.key_values().visit(&mut Fields(&mut fields));
+    serde_json::Value::Object(fields).to_string()
+}
+
+/*
+With LOG_FORMAT=json every log record is a single line of JSON, with the run id, user, start reason and phase
+Otherwise the pretty logger is used. RUST_LOG sets the level in both cases
+*/
+pub fn init_logging() {
+    if var("LOG_FORMAT").is_ok_and(|format| format.eq_ignore_ascii_case("json")) {
+        let user = var("USERNAME").unwrap_or_default();
+        let mut builder = pretty_env_logger::formatted_builder();
+        builder.format(move |buf, record| {
+            writeln!(buf, "{}", json_record(record, &user, &current_context()))
+        });
+        if let Ok(filters) = var("RUST_LOG") {
+            builder.parse_filters(&filters);
+        }
+        builder.init();
+    } else {
+        pretty_env_logger::init();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_records_carry_the_run_context() {
+        let context = RunContext {
+            run_id: Some("00000000000000ff".to_owned()),
+            start_reason: Some(StartReason::Timer),
+            phase: Some(Phase::FetchMonth),
+        };
+        let fields = [("function", Value::from("Loading month"))];
+        let record = Record::builder()
+            .args(format_args!("Month failed"))
+            .level(log::Level::Warn)
+            .target("webcom_ical")
+            .key_values(&fields)
+            .build();
+        let line: serde_json::Value =
+            serde_json::from_str(&json_record(&record, "1234", &context)).unwrap();
+        assert_eq!(line["message"], "Month failed");
+        assert_eq!(line["level"], "WARN");
+        assert_eq!(line["user"], "1234");
+        assert_eq!(line["run_id"], "00000000000000ff");
+        assert_eq!(line["start_reason"], "Timer");
+        assert_eq!(line["phase"], "fetch_month");
+        assert_eq!(line["function"], "Loading month");
+
+        let line: serde_json::Value =
+            serde_json::from_str(&json_record(&record, "1234", &RunContext::default())).unwrap();
+        assert!(line["run_id"].is_null());
+        assert!(line["phase"].is_null());
+    }
+}
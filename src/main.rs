@@ -11,6 +11,7 @@ extern crate pretty_env_logger;
 extern crate log;
 
 use clap::Parser;
+use clap::Subcommand;
 use clap::command;
 use dotenvy::dotenv_override;
 use dotenvy::var;
@@ -18,41 +19,88 @@ use email::send_errors;
 use email::send_welcome_mail;
 use std::fs;
 use std::fs::write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::LazyLock;
 use std::sync::RwLock;
-use thirtyfour::prelude::*;
+use time::Date;
 use time::macros::format_description;
 use tokio::spawn;
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::mpsc::channel;
+use tokio::time::sleep;
 
+use crate::absence::Absence;
+use crate::absence::AbsenceCalendar;
+use crate::absence::AbsenceStore;
+use crate::control::ControlRequest;
 use crate::errors::FailureType;
 use crate::errors::IncorrectCredentialsCount;
 use crate::errors::ResultLog;
 use crate::errors::SignInFailure;
+use crate::errors::WebcomError;
 use crate::execution::StartReason;
 use crate::execution::execution_manager;
-use crate::execution::start_pipe;
+use crate::export::ExportFormat;
+use crate::export::export_shifts;
+use crate::export::parse_date_argument;
+use crate::export::parse_month_argument;
+use crate::feeds::ShiftChange;
+use crate::feeds::ShiftChanges;
 use crate::health::ApplicationLogbook;
 use crate::health::send_heartbeat;
 use crate::health::update_calendar_exit_code;
+use crate::history::RunRecord;
 use crate::ical::*;
+use crate::logging::Phase;
+use crate::page_cache::PageCache;
 use crate::parsing::*;
+use crate::polling::ChangeHistory;
+use crate::retry::CircuitBreaker;
+use crate::retry::CircuitDecision;
+use crate::retry::FailureClass;
+use crate::retry::RetryPolicy;
+use crate::retry::RetryStep;
 use crate::shift::*;
+use crate::webcom_client::WebcomBackend;
+use crate::webcom_client::WebcomClient;
 
+mod absence;
+mod backfill;
+mod browser_client;
+mod control;
 pub mod email;
 pub mod errors;
 mod execution;
+mod export;
+#[cfg(test)]
+mod fake_client;
+mod feeds;
 pub mod gebroken_shifts;
 mod health;
+mod history;
+pub mod holidays;
+mod http_client;
 mod ical;
 pub mod kuma;
+mod logging;
+mod messages;
+mod metrics;
+#[cfg(any(test, feature = "mock-server"))]
+mod mock_server;
+mod page_cache;
 mod parsing;
+mod pdf;
+mod polling;
+mod rate_limit;
+mod retry;
+mod schedule;
 pub mod shift;
+mod status;
+mod team;
+mod webcom_client;
 
 type GenResult<T> = Result<T, GenError>;
-type GenError = Box<dyn std::error::Error + Send + Sync + 'static>;
+type GenError = errors::WebcomError;
 
 static NAME: LazyLock<RwLock<Option<String>>> = LazyLock::new(|| RwLock::new(None));
 
@@ -63,6 +111,83 @@ struct Args {
     instant_run: bool,
     #[arg(short, long)]
     single_run: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Export the stored shifts to CSV, JSON or Excel
+    Export {
+        #[arg(short, long, value_enum, default_value = "csv")]
+        format: ExportFormat,
+        /// First date to export, eg: 2025-01-01
+        #[arg(long, value_parser = parse_date_argument)]
+        from: Option<Date>,
+        /// Last date to export, eg: 2025-12-31
+        #[arg(long, value_parser = parse_date_argument)]
+        to: Option<Date>,
+        /// File to write to, CSV and JSON are written to stdout if not set
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Create a printable PDF roster of a single month
+    Pdf {
+        /// Month to print, eg: 2025-05. The current month if not set
+        #[arg(short, long, value_parser = parse_month_argument)]
+        month: Option<Date>,
+        /// File to write the PDF to
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Start a fake webcom server for testing, driven by a scenario file
+    #[cfg(feature = "mock-server")]
+    MockServer {
+        /// JSON file with the account, shifts and failures the fake webcom should show
+        #[arg(long)]
+        scenario: PathBuf,
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        address: String,
+    },
+    /// Load the history of every month since the given month, without sending mails
+    Backfill {
+        /// First month to load, eg: 2025-01
+        #[arg(long, value_parser = parse_month_argument)]
+        since: Date,
+    },
+    /// Show if a run is in progress, how the last run went and when the next run is
+    Status {
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Send a command to the running webcom ical
+    Ctl {
+        #[arg(value_enum)]
+        command: ControlRequest,
+        /// Control socket to use, kuma/control.sock if not set
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    },
+    /// Show the runs of the last days
+    History {
+        /// How many days back to show
+        #[arg(long, default_value = "7")]
+        days: i64,
+        /// Only show runs which did not end with Ok
+        #[arg(long)]
+        failures: bool,
+        /// Print every run as a line of JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write a calendar with the stored shifts of several users
+    Team {
+        /// JSON file with the name, output file and members of the team
+        #[arg(long)]
+        config: PathBuf,
+    },
 }
 
 fn create_shift_link(shift: &Shift, include_domain: bool) -> GenResult<String> {
@@ -97,57 +222,6 @@ fn create_ical_filename() -> GenResult<String> {
     }
 }
 
-pub async fn wait_until_loaded(driver: &WebDriver) -> GenResult<()> {
-    let mut started_loading = false;
-    let timeout_duration = std::time::Duration::from_secs(30);
-    let _ = tokio::time::timeout(timeout_duration, async {
-        loop {
-            let ready_state: ScriptRet =
-                driver.execute("return document.readyState", vec![]).await?;
-            let current_state = format!("{:?}", ready_state.json());
-            if current_state == "String(\"complete\")" && started_loading {
-                tokio::time::sleep(std::time::Duration::from_millis(500)).await;
-                return Ok::<(), WebDriverError>(());
-            }
-            if current_state == "String(\"loading\")" {
-                started_loading = true;
-            }
-            tokio::task::yield_now().await;
-        }
-    })
-    .await?;
-    Ok(())
-}
-
-async fn wait_untill_redirect(driver: &WebDriver) -> GenResult<()> {
-    let initial_url = driver.current_url().await?;
-    let mut current_url = driver.current_url().await?;
-    let timeout = std::time::Duration::from_secs(30); // Maximum wait time.
-
-    tokio::time::timeout(timeout, async {
-        loop {
-            let new_url = driver.current_url().await.unwrap();
-            if new_url != current_url {
-                current_url = new_url;
-                break;
-            }
-            tokio::task::yield_now().await;
-        }
-    })
-    .await?;
-
-    if current_url == initial_url {
-        warn!("Timeout waiting for redirect.");
-        return Err(Box::new(WebDriverError::Timeout(
-            "Redirect did not occur".into(),
-        )));
-    }
-
-    debug!("Redirected to: {}", current_url);
-    wait_until_loaded(driver).await?;
-    Ok(())
-}
-
 pub fn create_path(filename: &str) -> PathBuf {
     let mut path = PathBuf::from(BASE_DIRECTORY);
     path.push(filename);
@@ -181,35 +255,171 @@ fn set_get_name(set_new_name: Option<String>) -> String {
     name
 }
 
+/*
+Splits the shifts the way the user wants them in their calendar and writes the calendar to disk
+all_shifts are the unmodified shifts, these are used for the feeds
+Absences are only in the main calendar if ABSENCE_EVENTS is not set to separate or false
+The feeds in CALENDAR_FEEDS are written next to the main calendar
+*/
+fn write_calendar(
+    all_shifts: &Vec<Shift>,
+    absences: &[Absence],
+    changes: &[ShiftChange],
+    exit_code: &FailureType,
+) -> GenResult<()> {
+    let ical_path = get_ical_path()?;
+    let main_absences = match AbsenceCalendar::from_env() {
+        AbsenceCalendar::Main => absences,
+        _ => &[],
+    };
+    let broken_split_shifts = gebroken_shifts::split_broken_shifts(all_shifts);
+    let midnight_stopped_shifts = gebroken_shifts::stop_shift_at_midnight(&broken_split_shifts);
+    let mut night_split_shifts = gebroken_shifts::split_night_shift(&midnight_stopped_shifts);
+    night_split_shifts.sort_by_key(|shift| shift.magic_number);
+    night_split_shifts.dedup();
+    debug!("Saving {} shifts", night_split_shifts.len());
+    let calendar = create_ical(&night_split_shifts, main_absences, exit_code);
+    info!("Writing to: {:?}", &ical_path);
+    write(ical_path, calendar.as_bytes())?;
+    feeds::write_feeds(&night_split_shifts, all_shifts, absences, changes)
+        .warn("Writing calendar feeds");
+    Ok(())
+}
+
+// Signs in to the opened webcom and loads all shifts in the month range
+async fn load_webcom_shifts(
+    client: &mut impl WebcomClient,
+    username: &str,
+    password: &str,
+    logbook: &mut ApplicationLogbook,
+    page_cache: &mut PageCache,
+    absences: &mut AbsenceStore,
+    month_range: &MonthRange,
+) -> GenResult<Vec<Shift>> {
+    load_calendar(client, username, password).await?;
+    load_month_range_shifts(client, logbook, page_cache, absences, month_range).await
+}
+
+// A single run of a program which run_with_retries can retry
+trait RetryableProgram<C: WebcomClient> {
+    fn run(
+        &mut self,
+        client: &mut C,
+        retry_count: usize,
+    ) -> impl Future<Output = GenResult<()>> + Send;
+}
+
+struct MainProgram<'a> {
+    username: &'a str,
+    password: &'a str,
+    logbook: &'a mut ApplicationLogbook,
+}
+
+impl<C: WebcomClient> RetryableProgram<C> for MainProgram<'_> {
+    async fn run(&mut self, client: &mut C, retry_count: usize) -> GenResult<()> {
+        main_program(
+            client,
+            self.username,
+            self.password,
+            retry_count,
+            self.logbook,
+        )
+        .await
+    }
+}
+
+// What happened while running the main program, after all retries
+#[derive(Debug, Default)]
+struct RetryOutcome {
+    exit_code: FailureType,
+    // None if signing in was never reached
+    sign_in_result: Option<Result<(), SignInFailure>>,
+    errors: Vec<GenError>,
+    // Every failed try and what was decided after it
+    steps: Vec<RetryStep>,
+}
+
+/*
+Runs the program until it succeeds, or the retry policy gives up
+The class of the failure decides how often it is tried and how long to wait in between
+*/
+async fn run_with_retries<C: WebcomClient>(
+    client: &mut C,
+    policy: &RetryPolicy,
+    program: &mut impl RetryableProgram<C>,
+) -> RetryOutcome {
+    let mut outcome = RetryOutcome::default();
+    let mut attempt: usize = 0;
+    loop {
+        let error = match program
+            .run(client, attempt)
+            .await
+            .warn_owned("Main Program")
+        {
+            Ok(()) => {
+                outcome.sign_in_result = Some(Ok(()));
+                break;
+            }
+            Err(error) => error,
+        };
+        let class = FailureClass::of(&error);
+        let failures = outcome
+            .steps
+            .iter()
+            .filter(|step| step.class == class)
+            .count()
+            + 1;
+        let delay = policy.decide(class, failures);
+        outcome.steps.push(RetryStep {
+            attempt,
+            class,
+            error: error.to_string(),
+            delay_seconds: delay.map(|delay| delay.as_secs_f64()),
+        });
+        match error {
+            WebcomError::Failure(FailureType::SignInFailed(signin_failure)) => {
+                outcome.sign_in_result = Some(Err(signin_failure.clone()));
+                outcome.exit_code = FailureType::SignInFailed(signin_failure);
+            }
+            WebcomError::Failure(FailureType::ConnectError) => {
+                outcome.exit_code = FailureType::ConnectError;
+            }
+            error => {
+                outcome.errors.push(error);
+                outcome.exit_code = FailureType::TriesExceeded;
+            }
+        }
+        let Some(delay) = delay else {
+            return outcome;
+        };
+        info!(
+            "Trying again in {:.1} seconds after a {class:?} failure",
+            delay.as_secs_f64()
+        );
+        sleep(delay).await;
+        attempt += 1;
+    }
+    outcome.exit_code = FailureType::OK;
+    if outcome.steps.is_empty() {
+        info!("Alles is in een keer goed gegaan, jippie!");
+    } else {
+        warn!("Errors have occured, but succeded in the end");
+    }
+    outcome
+}
+
 // Main program logic that has to run, if it fails it will all be reran.
 async fn main_program(
-    driver: &WebDriver,
+    client: &mut impl WebcomClient,
     username: &str,
     password: &str,
     retry_count: usize,
     logbook: &mut ApplicationLogbook,
 ) -> GenResult<()> {
-    driver.delete_all_cookies().await?;
-    info!("Loading site: {}..", MAIN_URL);
-    match driver.goto(MAIN_URL).await {
-        Ok(_) => wait_untill_redirect(&driver).await?,
-        Err(_) => {
-            error!(
-                "Failed waiting for redirect. Going to fallback {}",
-                FALLBACK_URL[retry_count % FALLBACK_URL.len()]
-            );
-            driver
-                .goto(FALLBACK_URL[retry_count % FALLBACK_URL.len()])
-                .await
-                .map_err(|_| Box::new(FailureType::ConnectError))?
-        }
-    };
-    load_calendar(&driver, &username, &password).await?;
-    wait_until_loaded(&driver).await?;
-    let mut new_shifts = load_current_month_shifts(&driver, logbook).await?;
-    let mut non_relevant_shifts = vec![];
+    let mut month_range = MonthRange::from_env();
     let ical_path = get_ical_path()?;
-    if !ical_path.exists() {
+    let first_run = !ical_path.exists();
+    if first_run {
         info!(
             "Existing calendar file not found, adding two extra months of shifts and removing partial calendars"
         );
@@ -219,23 +429,33 @@ async fn main_program(
             Ok(())
         }()
         .info("Removing partial shifts");
-        let found_shifts = load_previous_month_shifts(&driver, 2).await?;
-        debug!("Found a total of {} shifts", found_shifts.len());
-        let mut found_shifts_split = split_relevant_shifts(found_shifts);
-        new_shifts.append(&mut found_shifts_split.0);
-        non_relevant_shifts.append(&mut found_shifts_split.1);
-        debug!(
-            "Got {} relevant and {} non-relevant events",
-            new_shifts.len(),
-            non_relevant_shifts.len()
-        );
+        month_range.months_back += 2;
     } else {
         debug!("Existing calendar file found");
-        new_shifts.append(&mut load_previous_month_shifts(&driver, 0).await?);
     }
-    new_shifts.append(&mut load_next_month_shifts(&driver, logbook).await?);
+    let mut page_cache = PageCache::load();
+    let mut absences = AbsenceStore::load();
+    client.open(retry_count).await?;
+    let found_shifts = load_webcom_shifts(
+        client,
+        username,
+        password,
+        logbook,
+        &mut page_cache,
+        &mut absences,
+        &month_range,
+    )
+    .await?;
+    debug!("Found a total of {} shifts", found_shifts.len());
+    let (new_shifts, mut non_relevant_shifts) = split_relevant_shifts(found_shifts);
+    debug!(
+        "Got {} relevant and {} non-relevant events",
+        new_shifts.len(),
+        non_relevant_shifts.len()
+    );
     info!("Found {} shifts", new_shifts.len());
     // If getting previous shift information failed, just create an empty one. Because it will cause a new calendar to be created
+    logging::set_phase(Phase::Diff);
     let mut previous_shifts_information = || -> Option<PreviousShiftInformation> {
         Some(
             get_previous_shifts()
@@ -244,41 +464,70 @@ async fn main_program(
         )
     }()
     .unwrap_or_default();
+    // Freshly loaded non-relevant shifts replace the stored ones of the same day
+    previous_shifts_information
+        .previous_non_relevant_shifts
+        .retain(|previous| {
+            !non_relevant_shifts
+                .iter()
+                .any(|shift| shift.date == previous.date)
+        });
     non_relevant_shifts.append(&mut previous_shifts_information.previous_non_relevant_shifts);
     let previous_shifts = previous_shifts_information.previous_relevant_shifts;
+    let mut shift_changes = ShiftChanges::load();
+    // Without previous shifts every shift looks new, so nothing is recorded as a change
+    let previous_shifts_for_changes = previous_shifts.clone();
     // The main send email function will return the broken shifts that are new or have changed.
     // This is because the send email functions uses the previous shifts and scanns for new shifts
     // write("./shifts.json",serde_json::to_string_pretty(&new_shifts).unwrap());
+    logging::set_phase(Phase::Mail);
     let relevant_shifts = match email::send_emails(new_shifts, previous_shifts) {
         Ok(shifts) => shifts,
         Err(err) => return Err(err),
     };
+    let today = time::OffsetDateTime::now_utc().date();
+    if !previous_shifts_for_changes.is_empty()
+        && shift_changes.record(&previous_shifts_for_changes, &relevant_shifts, today) > 0
+    {
+        // Adaptive polling learns from when changes are found
+        let mut change_history = ChangeHistory::load();
+        change_history
+            .record(chrono::Local::now().naive_local())
+            .warn("Recording change history");
+        change_history.save().warn("Saving change history");
+    }
+    shift_changes.prune(today, feeds::changes_feed_days());
+    shift_changes.save().warn("Saving shift changes");
     let mut all_shifts = relevant_shifts;
     let non_relevant_shift_len = non_relevant_shifts.len();
     all_shifts.append(&mut non_relevant_shifts);
-    let all_shifts = gebroken_shifts::load_broken_shift_information(&driver, &all_shifts).await?; // Replace the shifts with the newly created list of broken shifts
+    logging::set_phase(Phase::BrokenShifts);
+    let all_shifts =
+        gebroken_shifts::load_broken_shift_information(client, &all_shifts, &mut page_cache)
+            .await?; // Replace the shifts with the newly created list of broken shifts
+    page_cache.save().warn("Saving page cache");
+    logbook.set_page_cache_statistics(&page_cache.statistics);
+    info!("Page cache: {:?}", page_cache.statistics);
+    logging::set_phase(Phase::WriteIcs);
     ical::save_partial_shift_files(&all_shifts).error("Saving partial shift files");
-    let broken_split_shifts = gebroken_shifts::split_broken_shifts(&all_shifts);
-    let midnight_stopped_shifts = gebroken_shifts::stop_shift_at_midnight(&broken_split_shifts);
-    let mut night_split_shifts = gebroken_shifts::split_night_shift(&midnight_stopped_shifts);
-    night_split_shifts.sort_by_key(|shift| shift.magic_number);
-    night_split_shifts.dedup();
-    debug!("Saving {} shifts", night_split_shifts.len());
-    let calendar = create_ical(&night_split_shifts, &all_shifts, &logbook.state);
+    absences.save().warn("Saving absences");
     send_welcome_mail(&ical_path, false)?;
-    info!("Writing to: {:?}", &ical_path);
-    write(ical_path, calendar.as_bytes())?;
+    write_calendar(
+        &all_shifts,
+        absences.absences(),
+        shift_changes.changes(),
+        &logbook.state,
+    )?;
+    pdf::save_month_pdfs(&all_shifts).warn("Saving PDF rosters");
+    if let Ok(team_config) = var("TEAM_CONFIG")
+        && !team_config.is_empty()
+    {
+        team::write_team_calendar(Path::new(&team_config)).warn("Writing team calendar");
+    }
     logbook.generate_shift_statistics(&all_shifts, non_relevant_shift_len);
     Ok(())
 }
 
-async fn initiate_webdriver() -> GenResult<WebDriver> {
-    let gecko_ip = var("GECKO_IP")?;
-    let caps = DesiredCapabilities::firefox();
-    let driver = WebDriver::new(format!("http://{}", gecko_ip), caps).await?;
-    Ok(driver)
-}
-
 // Create file on disk to show webcom ical is currently active
 // Always delete the file at the beginning of this function
 // Only create a new file if start reason is Some
@@ -297,7 +546,7 @@ fn create_delete_lock(start_reason: Option<&StartReason>) -> GenResult<()> {
 }
 
 /*
-This starts the WebDriver session
+This starts the Webcom client
 Loads the main logic, and retries if it fails
 */
 async fn main_loop(receiver: &mut Receiver<StartReason>, kuma_url: Option<&str>) {
@@ -307,7 +556,11 @@ async fn main_loop(receiver: &mut Receiver<StartReason>, kuma_url: Option<&str>)
 
         dotenv_override().warn("Getting ENV");
 
+        // Held until the end of the run, so a backfill can not change the stored shifts at the same time
+        let _run_lock = status::lock_run().await.warn_owned("Taking run lock");
         create_delete_lock(Some(&continue_execution)).warn("Creating Lock file");
+        let run_id = logging::start_run(continue_execution);
+        info!(run_id:% = run_id; "Starting run {run_id}, reason: {continue_execution:?}");
 
         let name = set_get_name(None);
         let mut logbook = ApplicationLogbook::load();
@@ -315,8 +568,8 @@ async fn main_loop(receiver: &mut Receiver<StartReason>, kuma_url: Option<&str>)
 
         let username = var("USERNAME").expect("Error in username variable loop");
         let password = var("PASSWORD").expect("Error in password variable loop");
-        let driver = match get_driver(&mut logbook, &username).await {
-            Ok(driver) => driver,
+        let mut client = match get_client(&mut logbook, &username).await {
+            Ok(client) => client,
             Err(err) => {
                 error!("Failed to get driver! error: {}", err.to_string());
                 logbook
@@ -326,77 +579,74 @@ async fn main_loop(receiver: &mut Receiver<StartReason>, kuma_url: Option<&str>)
             }
         };
 
-        let mut current_exit_code = FailureType::default();
         let previous_exit_code = logbook.clone().state;
-        let mut running_errors: Vec<GenError> = vec![];
 
-        let mut retry_count: usize = 0;
-        let max_retry_count: usize = var("RETRY_COUNT")
-            .unwrap_or("3".to_string())
-            .parse()
-            .unwrap_or(3);
+        let retry_policy = RetryPolicy::from_env();
+        let mut circuit_breaker = CircuitBreaker::load();
 
         // Check if the program is allowed to run, or not due to failed sign-in
         let sign_in_check: Option<SignInFailure> =
             failure_counter.sign_in_failed_check().unwrap_or(None);
-        if continue_execution != StartReason::Force {
-            if let Some(failure) = sign_in_check {
-                retry_count = max_retry_count;
-                current_exit_code = FailureType::SignInFailed(failure);
+        let blocking_failure = match continue_execution != StartReason::Force {
+            true => sign_in_check,
+            false => {
+                info!("Force resuming execution");
+                None
             }
-        } else {
-            info!("Force resuming execution");
-        }
+        };
 
-        while retry_count < max_retry_count {
-            match main_program(&driver, &username, &password, retry_count, &mut logbook)
-                .await
-                .warn_owned("Main Program")
-            {
-                Ok(()) => {
-                    failure_counter
-                        .update_signin_failure(false, None)
-                        .warn("Updating signin failure");
-                    retry_count = max_retry_count;
-                }
-                Err(err) if err.downcast_ref::<FailureType>().is_some() => {
-                    let webcom_error = err
-                        .downcast_ref::<FailureType>()
-                        .cloned()
-                        .unwrap_or_default();
-                    match webcom_error.clone() {
-                        FailureType::SignInFailed(signin_failure) => {
-                            retry_count = max_retry_count;
-                            failure_counter
-                                .update_signin_failure(true, Some(signin_failure.clone()))
-                                .warn("Updating signin failure 2");
-                            current_exit_code = webcom_error;
-                        }
-                        FailureType::ConnectError => {
-                            retry_count = max_retry_count;
-                            current_exit_code = FailureType::ConnectError;
-                        }
-                        _ => {
-                            running_errors.push(err);
-                        }
-                    }
-                }
-                Err(err) => {
-                    running_errors.push(err);
-                }
-            };
-            retry_count += 1;
-        }
-        if running_errors.is_empty() {
-            info!("Alles is in een keer goed gegaan, jippie!");
-        } else if running_errors.len() < max_retry_count {
-            warn!("Errors have occured, but succeded in the end");
+        let paused_until = match continue_execution {
+            StartReason::Force => None,
+            _ => circuit_breaker.paused_until(chrono::Local::now()),
+        };
+
+        let mut retry_steps = vec![];
+        let mut run_errors = vec![];
+        let mut circuit_decision = None;
+        let mut current_exit_code = if let Some(failure) = blocking_failure {
+            FailureType::SignInFailed(failure)
+        } else if let Some(paused_until) = paused_until {
+            info!("Webcom was down the last runs, not trying again until {paused_until}");
+            circuit_decision = Some(CircuitDecision::Paused {
+                until: paused_until.to_rfc3339(),
+            });
+            FailureType::SignInFailed(SignInFailure::WebcomDown)
         } else {
-            current_exit_code = FailureType::TriesExceeded;
-            send_errors(&running_errors, &name).warn("Sending errors in loop");
-        }
+            let mut program = MainProgram {
+                username: &username,
+                password: &password,
+                logbook: &mut logbook,
+            };
+            let outcome = run_with_retries(&mut client, &retry_policy, &mut program).await;
+            match outcome.sign_in_result {
+                Some(Ok(())) => failure_counter
+                    .update_signin_failure(false, None)
+                    .warn("Updating signin failure"),
+                Some(Err(signin_failure)) => failure_counter
+                    .update_signin_failure(true, Some(signin_failure))
+                    .warn("Updating signin failure 2"),
+                None => (),
+            }
+            if outcome.exit_code == FailureType::TriesExceeded {
+                send_errors(&outcome.errors, &name).warn("Sending errors in loop");
+            }
+            circuit_decision = circuit_breaker.record(
+                &outcome.exit_code,
+                chrono::Local::now(),
+                retry::circuit_breaker_threshold(),
+                retry::circuit_breaker_pause(),
+            );
+            circuit_breaker.save().warn("Saving circuit breaker");
+            retry_steps = outcome.steps;
+            run_errors = outcome
+                .errors
+                .iter()
+                .map(|error| error.to_string())
+                .collect();
+            outcome.exit_code
+        };
 
-        _ = driver.quit().await.is_err_and(|_| {
+        _ = client.quit().await.is_err_and(|_| {
             current_exit_code = FailureType::GeckoEngine;
             true
         });
@@ -410,6 +660,15 @@ async fn main_loop(receiver: &mut Receiver<StartReason>, kuma_url: Option<&str>)
         logbook
             .save(&current_exit_code)
             .warn("Saving logbook in loop");
+        metrics::record_run(&current_exit_code, &logbook, &failure_counter);
+        history::append_run(&RunRecord {
+            retries: retry_steps.len(),
+            errors: run_errors,
+            retry_steps,
+            circuit_breaker: circuit_decision,
+            ..RunRecord::new(continue_execution, &current_exit_code, &logbook)
+        })
+        .warn("Appending run history");
 
         // Update the exit code in the calendar if it is not equal to the previous value
         if previous_exit_code != current_exit_code {
@@ -419,6 +678,7 @@ async fn main_loop(receiver: &mut Receiver<StartReason>, kuma_url: Option<&str>)
         }
 
         create_delete_lock(None).warn("Removing Lock file");
+        logging::end_run();
 
         if continue_execution == StartReason::Single {
             break;
@@ -426,10 +686,10 @@ async fn main_loop(receiver: &mut Receiver<StartReason>, kuma_url: Option<&str>)
     }
 }
 
-async fn get_driver(logbook: &mut ApplicationLogbook, username: &str) -> GenResult<WebDriver> {
+async fn get_client(logbook: &mut ApplicationLogbook, username: &str) -> GenResult<WebcomBackend> {
     let kuma_url = var("KUMA_URL").ok();
-    match initiate_webdriver().await {
-        Ok(driver) => Ok(driver),
+    match WebcomBackend::connect().await {
+        Ok(client) => Ok(client),
         Err(error) => {
             error!("Kon driver niet opstarten: {:?}", &error);
             send_errors(&vec![error], &set_get_name(None)).info("Send errors");
@@ -444,14 +704,50 @@ async fn get_driver(logbook: &mut ApplicationLogbook, username: &str) -> GenResu
     }
 }
 
+// Commands which do a single task and exit, instead of starting the main loop
+async fn run_command(command: Command) -> GenResult<()> {
+    match command {
+        Command::Export {
+            format,
+            from,
+            to,
+            output,
+        } => export_shifts(format, from, to, output),
+        Command::Pdf { month, output } => {
+            let month = month.unwrap_or(time::OffsetDateTime::now_utc().date());
+            let shifts = load_stored_shifts()?;
+            let pdf = pdf::create_month_pdf(&shifts, month.year(), month.month())?;
+            write(output, pdf)?;
+            Ok(())
+        }
+        Command::Backfill { since } => backfill::backfill(since).await,
+        Command::Status { json } => status::print_status(json),
+        Command::Ctl { command, socket } => control::run_ctl(command, socket).await,
+        Command::History {
+            days,
+            failures,
+            json,
+        } => history::print_history(days, failures, json),
+        Command::Team { config } => team::write_team_calendar(&config),
+        #[cfg(feature = "mock-server")]
+        Command::MockServer { scenario, address } => {
+            mock_server::run_mock_server(&scenario, &address).await
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> GenResult<()> {
     dotenv_override().ok();
-    pretty_env_logger::init();
+    logging::init_logging();
     info!("Starting Webcom Ical");
 
     let args = Args::parse();
 
+    if let Some(command) = args.command {
+        return run_command(command).await;
+    }
+
     let username = var("USERNAME").expect("Error in username variable");
     let kuma_url = var("KUMA_URL").ok();
 
@@ -472,15 +768,192 @@ async fn main() -> GenResult<()> {
             spawn(async move { execution_manager(tx, instant_run).await });
         }
         true => {
-            tx.send(StartReason::Single).await?;
+            tx.send(StartReason::Single)
+                .await
+                .map_err(|_| "Notification channel closed")?;
         }
     };
     let main_program = spawn(async move { main_loop(&mut rx, kuma_url.as_deref()).await });
     if !args.single_run {
-        start_pipe(tx_clone).await.warn("Start pipe");
+        spawn(async { metrics::start_metrics_server().await.warn("Metrics server") });
+        // Without the control socket the timer still starts runs, so webcom ical keeps running
+        if control::start_control_socket(tx_clone)
+            .await
+            .warn_owned("Control socket")
+            .is_err()
+        {
+            main_program.await.info("Main program");
+        }
     } else {
         main_program.await.info("Main program");
     }
     info!("Stopping webcom ical");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{absence::DayStatus, fake_client::FakeClient, health::MonthStatistics};
+
+    // Only loads the shifts from webcom, so nothing is written to disk
+    #[derive(Default)]
+    struct LoadProgram {
+        logbook: ApplicationLogbook,
+        page_cache: PageCache,
+        absences: AbsenceStore,
+        shifts: Vec<Shift>,
+    }
+
+    impl RetryableProgram<FakeClient> for LoadProgram {
+        async fn run(&mut self, client: &mut FakeClient, retry_count: usize) -> GenResult<()> {
+            let month_range = MonthRange {
+                months_back: 1,
+                months_ahead: 1,
+                stop_at_empty_month: false,
+            };
+            client.open(retry_count).await?;
+            self.shifts = load_webcom_shifts(
+                client,
+                "12345",
+                "wachtwoord",
+                &mut self.logbook,
+                &mut self.page_cache,
+                &mut self.absences,
+                &month_range,
+            )
+            .await?;
+            Ok(())
+        }
+    }
+
+    fn month_start(offset: i32) -> Date {
+        let (year, month) = offset_month(time::OffsetDateTime::now_utc().date(), offset);
+        Date::from_calendar_date(year, month, 1).unwrap()
+    }
+
+    async fn run(client: &mut FakeClient) -> (RetryOutcome, LoadProgram) {
+        let mut program = LoadProgram::default();
+        let policy = RetryPolicy {
+            base_delay: std::time::Duration::ZERO,
+            ..RetryPolicy::default()
+        };
+        let outcome = run_with_retries(client, &policy, &mut program).await;
+        (outcome, program)
+    }
+
+    #[tokio::test]
+    async fn successful_run_loads_all_months() {
+        let mut client = FakeClient::default();
+        client.add_shift(month_start(0), "V2309", "06:14", "13:54");
+        client.add_shift(month_start(1), "V2310", "14:00", "21:40");
+        let (outcome, program) = run(&mut client).await;
+        assert_eq!(outcome.exit_code, FailureType::OK);
+        assert_eq!(outcome.sign_in_result, Some(Ok(())));
+        assert!(outcome.errors.is_empty());
+        assert_eq!(program.shifts.len(), 2);
+        assert_eq!(client.fetched_months.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn absences_are_loaded_next_to_shifts() {
+        let mut client = FakeClient::default();
+        client.add_shift(month_start(0), "V2309", "06:14", "13:54");
+        client.add_day(month_start(0) + time::Duration::days(1), "Vrij");
+        client.add_day(month_start(1), "Verlof\u{a0}• \u{a0}• Vakantie");
+        client.add_day(month_start(1) + time::Duration::days(1), "");
+        let (outcome, program) = run(&mut client).await;
+        assert_eq!(outcome.exit_code, FailureType::OK);
+        assert_eq!(program.shifts.len(), 1);
+        let statuses: Vec<&DayStatus> = program
+            .absences
+            .absences()
+            .iter()
+            .map(|absence| &absence.status)
+            .collect();
+        assert_eq!(statuses, [&DayStatus::DayOff, &DayStatus::Leave]);
+    }
+
+    #[tokio::test]
+    async fn failing_to_acknowledge_messages_does_not_fail_the_run() {
+        let mut client = FakeClient {
+            acknowledge_error: true,
+            ..Default::default()
+        };
+        client.add_shift(month_start(0), "V2309", "06:14", "13:54");
+        let (outcome, program) = run(&mut client).await;
+        assert_eq!(outcome.exit_code, FailureType::OK);
+        assert_eq!(client.logins, 1);
+        assert_eq!(program.shifts.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn sign_in_failures_are_not_retried() {
+        for failure in [
+            SignInFailure::IncorrectCredentials,
+            SignInFailure::TooManyTries,
+            SignInFailure::WebcomDown,
+        ] {
+            let mut client = FakeClient {
+                sign_in_failure: Some(failure.clone()),
+                ..Default::default()
+            };
+            let (outcome, _) = run(&mut client).await;
+            assert_eq!(
+                outcome.exit_code,
+                FailureType::SignInFailed(failure.clone())
+            );
+            assert_eq!(outcome.sign_in_result, Some(Err(failure)));
+            assert_eq!(client.logins, 1);
+            assert!(client.fetched_months.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn connect_error_is_not_retried() {
+        let mut client = FakeClient {
+            connect_error: true,
+            ..Default::default()
+        };
+        let (outcome, _) = run(&mut client).await;
+        assert_eq!(outcome.exit_code, FailureType::ConnectError);
+        assert_eq!(outcome.sign_in_result, None);
+        assert_eq!(client.opened, 1);
+        assert_eq!(client.logins, 0);
+    }
+
+    #[tokio::test]
+    async fn partial_month_is_retried() {
+        let mut client = FakeClient::default();
+        client.add_shift(month_start(0), "V2309", "06:14", "13:54");
+        let (year, month) = offset_month(month_start(0), 1);
+        client.failing_months.insert((year, month), 1);
+        let (outcome, program) = run(&mut client).await;
+        assert_eq!(outcome.exit_code, FailureType::OK);
+        assert_eq!(outcome.errors.len(), 1);
+        assert_eq!(client.logins, 2);
+        assert_eq!(program.shifts.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn month_which_keeps_failing_exceeds_tries() {
+        let mut client = FakeClient::default();
+        let (year, month) = offset_month(month_start(0), -1);
+        client.failing_months.insert((year, month), usize::MAX);
+        let (outcome, program) = run(&mut client).await;
+        assert_eq!(outcome.exit_code, FailureType::TriesExceeded);
+        assert_eq!(outcome.errors.len(), 3);
+        assert_eq!(outcome.sign_in_result, None);
+        // The months after the failing month are not loaded, as the run is tried again anyway
+        let months = &program.logbook.application_state.months;
+        assert_eq!(months.len(), 2);
+        let failed_months: Vec<&MonthStatistics> = months
+            .iter()
+            .filter(|month| month.error.is_some())
+            .collect();
+        assert_eq!(failed_months.len(), 1);
+        assert_eq!(failed_months[0].month, format!("{year}-{:02}", month as u8));
+        let next_month = offset_month(month_start(0), 1);
+        assert!(!client.fetched_months.contains(&next_month));
+    }
+}
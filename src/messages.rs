@@ -0,0 +1,162 @@
+use std::fs::{read_to_string, write};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{GenResult, create_path, email::send_webcom_messages_mail};
+
+/*
+The messages page webcom shows after signing in when there are unread messages
+The markup of this page is not documented, and these ids have not been checked against a recorded page yet, so every selector is kept here
+The table has a row for every message with the columns: date, title, body
+Reading or acknowledging the messages only logs a warning when it fails, so a wrong id can not fail the sign in
+*/
+pub const MESSAGES_PATH: &str = "/WebComm/messages.aspx";
+pub const MESSAGE_TABLE_ID: &str = "ctl00_cntMainBody_gvMessages";
+// Some messages have to be marked as read before the roster can be opened
+pub const ACKNOWLEDGE_BUTTON_ID: &str = "ctl00_cntMainBody_btnRead";
+
+const MESSAGES_FILENAME: &str = "messages.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct WebcomMessage {
+    pub date: String,
+    pub title: String,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredMessage {
+    #[serde(flatten)]
+    message: WebcomMessage,
+    #[serde(with = "time::serde::rfc3339")]
+    received: OffsetDateTime,
+}
+
+// Every message webcom has shown, so users are only notified of a message once
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MessageStore {
+    messages: Vec<StoredMessage>,
+}
+
+impl MessageStore {
+    pub fn load() -> Self {
+        let path = create_path(MESSAGES_FILENAME);
+        match read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|error| {
+                warn!("Stored webcom messages could not be read, starting over. Error: {error}");
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self) -> GenResult<()> {
+        write(
+            create_path(MESSAGES_FILENAME),
+            serde_json::to_string_pretty(self)?.as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    // Adds the messages which have not been seen before, and returns them
+    pub fn add_new(&mut self, messages: &[WebcomMessage]) -> Vec<WebcomMessage> {
+        let mut new_messages = vec![];
+        for message in messages {
+            if self
+                .messages
+                .iter()
+                .any(|stored| &stored.message == message)
+                || new_messages.contains(message)
+            {
+                continue;
+            }
+            new_messages.push(message.clone());
+        }
+        let received = OffsetDateTime::now_utc();
+        self.messages
+            .extend(new_messages.iter().map(|message| StoredMessage {
+                message: message.clone(),
+                received,
+            }));
+        new_messages
+    }
+}
+
+/*
+Turns the rows of the message table into messages
+Rows without columns are the header of the table and are skipped
+*/
+pub fn parse_message_rows(rows: Vec<Vec<String>>) -> Vec<WebcomMessage> {
+    rows.into_iter()
+        .filter(|row| row.iter().any(|column| !column.trim().is_empty()))
+        .map(|row| {
+            let mut columns = row.into_iter().map(|column| column.trim().to_owned());
+            WebcomMessage {
+                date: columns.next().unwrap_or_default(),
+                title: columns.next().unwrap_or_default(),
+                body: columns.collect::<Vec<String>>().join("\n"),
+            }
+        })
+        .collect()
+}
+
+// Stores the messages webcom showed and mails the ones which are new
+pub fn handle_messages(messages: &[WebcomMessage]) -> GenResult<()> {
+    let mut store = MessageStore::load();
+    let new_messages = store.add_new(messages);
+    if new_messages.is_empty() {
+        debug!("No new webcom messages");
+        return Ok(());
+    }
+    info!("Found {} new webcom message(s)", new_messages.len());
+    // Only saved once the mail is sent, otherwise the messages are mailed by the next run
+    send_webcom_messages_mail(&new_messages)?;
+    store.save()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(title: &str) -> WebcomMessage {
+        WebcomMessage {
+            date: "02-06-2025".to_owned(),
+            title: title.to_owned(),
+            body: "Omleiding".to_owned(),
+        }
+    }
+
+    #[test]
+    fn message_rows_are_parsed() {
+        let rows = vec![
+            vec![],
+            vec![
+                " 02-06-2025 ".to_owned(),
+                "Werkzaamheden".to_owned(),
+                "Lijn 401 rijdt om".to_owned(),
+            ],
+        ];
+        assert_eq!(
+            parse_message_rows(rows),
+            vec![WebcomMessage {
+                date: "02-06-2025".to_owned(),
+                title: "Werkzaamheden".to_owned(),
+                body: "Lijn 401 rijdt om".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn messages_are_only_new_once() {
+        let mut store = MessageStore::default();
+        assert_eq!(
+            store.add_new(&[message("A"), message("A"), message("B")]),
+            vec![message("A"), message("B")]
+        );
+        assert_eq!(
+            store.add_new(&[message("B"), message("C")]),
+            vec![message("C")]
+        );
+    }
+}
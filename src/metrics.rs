@@ -0,0 +1,288 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs,
+    sync::{LazyLock, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use dotenvy::var;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    FailureType, GenResult, create_path,
+    errors::{IncorrectCredentialsCount, ResultLog},
+    health::ApplicationLogbook,
+};
+
+static METRICS: LazyLock<Mutex<Metrics>> = LazyLock::new(|| Mutex::new(Metrics::load()));
+
+// Counters only count since webcom ical started, gauges are the values of the last run
+#[derive(Debug, Default)]
+struct Metrics {
+    runs: BTreeMap<&'static str, u64>,
+    mails: BTreeMap<&'static str, u64>,
+    // The count and total seconds of every kind of page loaded from webcom
+    page_loads: BTreeMap<&'static str, (u64, f64)>,
+    execution_time_ms: u64,
+    shifts: u64,
+    broken_shifts: u64,
+    failed_shifts: u64,
+    failed_broken_shifts: u64,
+    sign_in_retry_count: usize,
+    // Seconds since the unix epoch
+    last_success: Option<f64>,
+}
+
+impl Metrics {
+    // The gauges start with the values of the last run before webcom ical started
+    fn load() -> Self {
+        let mut metrics = Self::default();
+        let logbook = ApplicationLogbook::load();
+        metrics.set_gauges(&logbook, &IncorrectCredentialsCount::load());
+        if logbook.state == FailureType::OK {
+            metrics.last_success = fs::metadata(create_path("logbook.json"))
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|modified| modified.as_secs_f64());
+        }
+        metrics
+    }
+
+    fn set_gauges(&mut self, logbook: &ApplicationLogbook, sign_in: &IncorrectCredentialsCount) {
+        let state = &logbook.application_state;
+        self.execution_time_ms = state.execution_time_ms;
+        self.shifts = state.shifts;
+        self.broken_shifts = state.broken_shifts;
+        self.failed_shifts = state.failed_shifts;
+        self.failed_broken_shifts = state.failed_broken_shifts;
+        self.sign_in_retry_count = sign_in.retry_count;
+    }
+
+    // The metrics in the prometheus text format, every metric has the user as label
+    fn render(&self, user: &str, now: f64) -> String {
+        let mut text = String::new();
+        // Every value is the suffix of the sample name, like _count of a summary, its labels and the value itself
+        let mut metric =
+            |name: &str, kind: &str, help: &str, values: Vec<(&str, String, String)>| {
+                _ = writeln!(text, "# HELP webcom_ical_{name} {help}");
+                _ = writeln!(text, "# TYPE webcom_ical_{name} {kind}");
+                for (suffix, labels, value) in values {
+                    _ = writeln!(
+                        text,
+                        "webcom_ical_{name}{suffix}{{user=\"{user}\"{labels}}} {value}"
+                    );
+                }
+            };
+        let labelled = |label: &str, counts: &BTreeMap<&'static str, u64>| {
+            counts
+                .iter()
+                .map(|(value, count)| ("", format!(",{label}=\"{value}\""), count.to_string()))
+                .collect()
+        };
+        let single = |value: String| vec![("", String::new(), value)];
+        metric(
+            "runs_total",
+            "counter",
+            "Finished runs by result",
+            labelled("result", &self.runs),
+        );
+        metric(
+            "mails_sent_total",
+            "counter",
+            "Sent mails by type",
+            labelled("type", &self.mails),
+        );
+        metric(
+            "run_duration_seconds",
+            "gauge",
+            "How long the last run took",
+            single((self.execution_time_ms as f64 / 1000.0).to_string()),
+        );
+        metric(
+            "shifts",
+            "gauge",
+            "Shifts found in the last run",
+            single(self.shifts.to_string()),
+        );
+        metric(
+            "broken_shifts",
+            "gauge",
+            "Broken shifts found in the last run",
+            single(self.broken_shifts.to_string()),
+        );
+        metric(
+            "failed_shifts",
+            "gauge",
+            "Shifts which could not be read in the last run",
+            single(self.failed_shifts.to_string()),
+        );
+        metric(
+            "failed_broken_shifts",
+            "gauge",
+            "Broken shifts without break times in the last run",
+            single(self.failed_broken_shifts.to_string()),
+        );
+        metric(
+            "sign_in_retry_count",
+            "gauge",
+            "Failed sign ins in a row",
+            single(self.sign_in_retry_count.to_string()),
+        );
+        metric(
+            "page_load_seconds",
+            "summary",
+            "Pages loaded from webcom and the time spent loading them",
+            self.page_loads
+                .iter()
+                .flat_map(|(page, (count, seconds))| {
+                    let labels = format!(",page=\"{page}\"");
+                    [
+                        ("_count", labels.clone(), count.to_string()),
+                        ("_sum", labels, seconds.to_string()),
+                    ]
+                })
+                .collect(),
+        );
+        if let Some(last_success) = self.last_success {
+            metric(
+                "seconds_since_last_success",
+                "gauge",
+                "Time since the calendar was last updated successfully",
+                single((now - last_success).max(0.0).round().to_string()),
+            );
+        }
+        text
+    }
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+fn update(change: impl FnOnce(&mut Metrics)) {
+    if let Ok(mut metrics) = METRICS.lock() {
+        change(&mut metrics);
+    }
+}
+
+// A short label for the result of a run
+fn failure_label(failure: &FailureType) -> &'static str {
+    match failure {
+        FailureType::OK => "ok",
+        FailureType::TriesExceeded => "tries_exceeded",
+        FailureType::GeckoEngine => "gecko_engine",
+        FailureType::SignInFailed(_) => "sign_in_failed",
+        FailureType::ConnectError => "connect_error",
+        FailureType::Other(_) => "other",
+    }
+}
+
+// Called after every run, with the saved logbook of that run
+pub fn record_run(
+    result: &FailureType,
+    logbook: &ApplicationLogbook,
+    sign_in: &IncorrectCredentialsCount,
+) {
+    update(|metrics| {
+        *metrics.runs.entry(failure_label(result)).or_default() += 1;
+        metrics.set_gauges(logbook, sign_in);
+        if result == &FailureType::OK {
+            metrics.last_success = Some(unix_now());
+        }
+    });
+}
+
+pub fn record_mail(kind: &'static str) {
+    update(|metrics| *metrics.mails.entry(kind).or_default() += 1);
+}
+
+pub fn record_page_load(page: &'static str, duration: Duration) {
+    update(|metrics| {
+        let load = metrics.page_loads.entry(page).or_default();
+        load.0 += 1;
+        load.1 += duration.as_secs_f64();
+    });
+}
+
+async fn handle_connection(mut stream: TcpStream) -> GenResult<()> {
+    let mut request = vec![0; 1024];
+    let length = stream.read(&mut request).await?;
+    let request = String::from_utf8_lossy(&request[..length]);
+    let (status, body) = match request.split_whitespace().nth(1) {
+        Some("/metrics") => {
+            let user = var("USERNAME").unwrap_or_default();
+            let body = METRICS
+                .lock()
+                .map(|metrics| metrics.render(&user, unix_now()))
+                .unwrap_or_default();
+            ("200 OK", body)
+        }
+        _ => ("404 Not Found", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+// Serves /metrics on METRICS_ADDRESS, does nothing if it is not set
+pub async fn start_metrics_server() -> GenResult<()> {
+    let Some(address) = var("METRICS_ADDRESS")
+        .ok()
+        .filter(|address| !address.is_empty())
+    else {
+        return Ok(());
+    };
+    let listener = TcpListener::bind(&address).await?;
+    info!("Serving metrics on http://{address}/metrics");
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            handle_connection(stream)
+                .await
+                .warn("Handling metrics request");
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_are_rendered_per_user() {
+        let mut metrics = Metrics::default();
+        *metrics
+            .runs
+            .entry(failure_label(&FailureType::OK))
+            .or_default() += 2;
+        *metrics.mails.entry("new_shift").or_default() += 1;
+        metrics.page_loads.insert("month", (2, 1.5));
+        metrics.shifts = 40;
+        metrics.last_success = Some(100.0);
+        let text = metrics.render("1234", 160.0);
+        for line in [
+            "# TYPE webcom_ical_runs_total counter",
+            "webcom_ical_runs_total{user=\"1234\",result=\"ok\"} 2",
+            "webcom_ical_mails_sent_total{user=\"1234\",type=\"new_shift\"} 1",
+            "webcom_ical_shifts{user=\"1234\"} 40",
+            "# TYPE webcom_ical_page_load_seconds summary",
+            "webcom_ical_page_load_seconds_count{user=\"1234\",page=\"month\"} 2",
+            "webcom_ical_page_load_seconds_sum{user=\"1234\",page=\"month\"} 1.5",
+            "webcom_ical_seconds_since_last_success{user=\"1234\"} 60",
+        ] {
+            assert!(text.contains(line), "{line} is missing from:\n{text}");
+        }
+    }
+}
@@ -0,0 +1,655 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+#[cfg(feature = "mock-server")]
+use std::{fs::read_to_string, path::Path};
+
+use serde::{Deserialize, Serialize};
+use time::{Date, Month};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use url::form_urlencoded;
+
+use crate::{
+    GenResult,
+    errors::ResultLog,
+    messages::{ACKNOWLEDGE_BUTTON_ID, MESSAGE_TABLE_ID},
+    shift::TooltipShift,
+};
+
+const VIEWSTATE: &str = "bW9jayB2aWV3c3RhdGU=";
+const EVENT_VALIDATION: &str = "bW9jayB2YWxpZGF0aW9u";
+const SESSION_COOKIE: &str = "ASP.NET_SessionId";
+const INCORRECT_CREDENTIALS_TEXT: &str =
+    "Uw aanmelding was niet succesvol. Voer a.u.b. het personeelsnummer of 'naam, voornaam' in";
+const TOO_MANY_TRIES_TEXT: &str = "Te veel verkeerde aanmeldpogingen";
+const WEBCOM_DOWN_TEXT: &str = "De servertoepassing is niet beschikbaar.";
+
+// A shift shown in the roster of the mock server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MockShift {
+    pub day: u8,
+    pub number: String,
+    pub start: String,
+    pub end: String,
+    #[serde(default = "default_duration")]
+    pub duration: String,
+    #[serde(default)]
+    pub kind: String,
+    #[serde(default)]
+    pub location: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+// A message shown on the messages page after signing in
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MockMessage {
+    pub date: String,
+    pub title: String,
+    pub body: String,
+}
+
+// A day which is not a shift, the tooltip is shown as is, like Vrij or Verlof
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MockDay {
+    pub day: u8,
+    pub tooltip: String,
+}
+
+fn default_duration() -> String {
+    "08:00".to_owned()
+}
+
+/*
+Everything the mock server shows, loaded from a JSON scenario file
+Months are written as YYYY-MM, shift pages as DD-MM-YYYY, just like in the URLs of webcom
+*/
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MockScenario {
+    pub username: String,
+    pub password: String,
+    pub name: String,
+    // Every page shows the outage message of webcom
+    pub webcom_down: bool,
+    // Every sign in attempt is refused because of too many attempts
+    pub too_many_tries: bool,
+    // Redirect to the messages page after signing in
+    pub show_messages: bool,
+    pub messages: Vec<MockMessage>,
+    // The roster can only be opened after the messages are marked as read
+    pub acknowledge_messages: bool,
+    pub months: HashMap<String, Vec<MockShift>>,
+    // Days off, leave and other absences, per month like the shifts
+    pub days: HashMap<String, Vec<MockDay>>,
+    // The rows of the trip table, every row has the columns: activity, start, location, end
+    pub shift_pages: HashMap<String, Vec<Vec<String>>>,
+}
+
+#[cfg(feature = "mock-server")]
+impl MockScenario {
+    pub fn load(path: &Path) -> GenResult<Self> {
+        Ok(serde_json::from_str(&read_to_string(path)?)?)
+    }
+}
+
+struct MockRequest {
+    method: String,
+    path: String,
+    query: String,
+    cookies: HashMap<String, String>,
+    body: String,
+}
+
+#[derive(Debug)]
+struct MockResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl MockResponse {
+    fn html(body: String) -> Self {
+        Self {
+            status: 200,
+            headers: vec![],
+            body,
+        }
+    }
+
+    fn redirect(location: &str) -> Self {
+        Self {
+            status: 302,
+            headers: vec![("Location".to_owned(), location.to_owned())],
+            body: String::new(),
+        }
+    }
+
+    fn status(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: body.to_owned(),
+        }
+    }
+}
+
+/*
+A fake webcom, so the scraper can be tested without the real webcom.connexxion.nl
+Only the pages and fields webcom ical uses are served
+*/
+pub struct MockWebcom {
+    scenario: MockScenario,
+    // Every session, and whether it has marked the messages as read
+    sessions: Mutex<HashMap<String, bool>>,
+}
+
+impl MockWebcom {
+    pub fn new(scenario: MockScenario) -> Self {
+        Self {
+            scenario,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn respond(&self, request: &MockRequest) -> MockResponse {
+        if request.path == "/" {
+            return MockResponse::redirect("/WebComm/default.aspx");
+        }
+        if self.scenario.webcom_down && request.path.starts_with("/WebComm/") {
+            return MockResponse::html(page(&format!("<h3>{WEBCOM_DOWN_TEXT}</h3>")));
+        }
+        let session = request.cookies.get(SESSION_COOKIE);
+        let acknowledged = session.and_then(|session| {
+            self.sessions
+                .lock()
+                .ok()
+                .and_then(|sessions| sessions.get(session).copied())
+        });
+        let must_read_messages = self.scenario.acknowledge_messages && acknowledged == Some(false);
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/WebComm/default.aspx") => MockResponse::html(login_page(None)),
+            ("POST", "/WebComm/default.aspx") => self.sign_in(request),
+            (_, "/WebComm/default.aspx") => MockResponse::status(405, "Method not allowed"),
+            (_, _) if acknowledged.is_none() => MockResponse::redirect("/WebComm/default.aspx"),
+            ("GET", "/WebComm/messages.aspx") => MockResponse::html(self.messages_page()),
+            ("POST", "/WebComm/messages.aspx") => self.read_messages(request, session),
+            ("GET", _) if must_read_messages => MockResponse::redirect("/WebComm/messages.aspx"),
+            ("GET", "/WebComm/home.aspx") => MockResponse::html(page(&format!(
+                "<h3>Goedemorgen, {}</h3><a href=\"roster.aspx\">Rooster</a>",
+                escape_html(&self.scenario.name)
+            ))),
+            ("GET", "/WebComm/roster.aspx") => self.roster_page(&request.query),
+            ("GET", "/WebComm/shift.aspx") => self.shift_page(&request.query),
+            _ => MockResponse::status(404, "Not found"),
+        }
+    }
+
+    fn sign_in(&self, request: &MockRequest) -> MockResponse {
+        let form: HashMap<String, String> = form_urlencoded::parse(request.body.as_bytes())
+            .into_owned()
+            .collect();
+        let field = |name: &str| form.get(name).map(String::as_str).unwrap_or_default();
+        // Just like ASP.NET, a postback without the hidden state fields is refused
+        if field("__VIEWSTATE") != VIEWSTATE || field("__EVENTVALIDATION") != EVENT_VALIDATION {
+            return MockResponse::status(500, "Validation of viewstate MAC failed");
+        }
+        if self.scenario.too_many_tries {
+            return MockResponse::html(login_page(Some(TOO_MANY_TRIES_TEXT)));
+        }
+        if field("ctl00$cntMainBody$lgnView$lgnLogin$UserName") != self.scenario.username
+            || field("ctl00$cntMainBody$lgnView$lgnLogin$Password") != self.scenario.password
+        {
+            return MockResponse::html(login_page(Some(INCORRECT_CREDENTIALS_TEXT)));
+        }
+        let session = format!("{:016x}", rand::random::<u64>());
+        if let Ok(mut sessions) = self.sessions.lock() {
+            sessions.insert(session.clone(), false);
+        }
+        let location = match self.scenario.show_messages {
+            true => "/WebComm/messages.aspx",
+            false => "/WebComm/home.aspx",
+        };
+        let mut response = MockResponse::redirect(location);
+        response.headers.push((
+            "Set-Cookie".to_owned(),
+            format!("{SESSION_COOKIE}={session}; path=/; HttpOnly"),
+        ));
+        response
+    }
+
+    fn messages_page(&self) -> String {
+        let mut rows = String::new();
+        for message in &self.scenario.messages {
+            rows.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                escape_html(&message.date),
+                escape_html(&message.title),
+                escape_html(&message.body)
+            ));
+        }
+        let button = match self.scenario.acknowledge_messages {
+            true => format!(
+                "<input type=\"submit\" name=\"ctl00$cntMainBody$btnRead\" value=\"Gelezen\" id=\"{ACKNOWLEDGE_BUTTON_ID}\" />"
+            ),
+            false => "<a href=\"home.aspx\">Verder</a>".to_owned(),
+        };
+        page(&format!(
+            r#"<h3>Berichten</h3>
+<form method="post" action="./messages.aspx" id="aspnetForm">
+<input type="hidden" name="__VIEWSTATE" id="__VIEWSTATE" value="{VIEWSTATE}" />
+<input type="hidden" name="__EVENTVALIDATION" id="__EVENTVALIDATION" value="{EVENT_VALIDATION}" />
+<table id="{MESSAGE_TABLE_ID}"><tbody>
+<tr><th>Datum</th><th>Onderwerp</th><th>Bericht</th></tr>
+{rows}</tbody></table>
+{button}
+</form>"#
+        ))
+    }
+
+    fn read_messages(&self, request: &MockRequest, session: Option<&String>) -> MockResponse {
+        let form: HashMap<String, String> = form_urlencoded::parse(request.body.as_bytes())
+            .into_owned()
+            .collect();
+        if form.get("__VIEWSTATE").map(String::as_str) != Some(VIEWSTATE) {
+            return MockResponse::status(500, "Validation of viewstate MAC failed");
+        }
+        if !form.contains_key("ctl00$cntMainBody$btnRead") {
+            return MockResponse::redirect("/WebComm/messages.aspx");
+        }
+        if let Some(session) = session
+            && let Ok(mut sessions) = self.sessions.lock()
+        {
+            sessions.insert(session.clone(), true);
+        }
+        MockResponse::redirect("/WebComm/home.aspx")
+    }
+
+    // The query looks like 2025-6-01, without a query the current month is shown
+    fn roster_page(&self, query: &str) -> MockResponse {
+        let today = time::OffsetDateTime::now_utc().date();
+        let mut parts = query.split('-').map(|part| part.parse::<i32>().ok());
+        let (year, month) = match (parts.next().flatten(), parts.next().flatten()) {
+            (Some(year), Some(month)) => match Month::try_from(month as u8) {
+                Ok(month) => (year, month),
+                Err(_) => return MockResponse::status(400, "Invalid month"),
+            },
+            _ => (today.year(), today.month()),
+        };
+        let month_key = format!("{year}-{:02}", month as u8);
+        let shifts = self
+            .scenario
+            .months
+            .get(&month_key)
+            .cloned()
+            .unwrap_or_default();
+        let other_days = self
+            .scenario
+            .days
+            .get(&month_key)
+            .cloned()
+            .unwrap_or_default();
+        let mut days = String::new();
+        for day in 1..=time::util::days_in_month(month, year) {
+            let tooltip = match shifts.iter().find(|shift| shift.day == day) {
+                Some(shift) => match Date::from_calendar_date(year, month, day) {
+                    Ok(date) => shift_tooltip(date, shift),
+                    Err(_) => String::new(),
+                },
+                None => other_days
+                    .iter()
+                    .find(|other_day| other_day.day == day)
+                    .map(|other_day| other_day.tooltip.clone())
+                    .unwrap_or_default(),
+            };
+            days.push_str(&format!(
+                "<td class=\"calDay\" data-original-title=\"{}\"><strong>{day}</strong></td>\n",
+                escape_html(&tooltip)
+            ));
+        }
+        MockResponse::html(page(&format!(
+            "<h3>Rooster {year}-{:02}</h3><table><tbody><tr>\n{days}</tr></tbody></table>",
+            month as u8
+        )))
+    }
+
+    fn shift_page(&self, query: &str) -> MockResponse {
+        let rows = match self.scenario.shift_pages.get(query) {
+            Some(rows) => rows,
+            None => return MockResponse::status(404, "Shift not found"),
+        };
+        let mut table_rows = String::new();
+        for row in rows {
+            table_rows.push_str("<tr>");
+            for column in row {
+                table_rows.push_str(&format!("<td>{}</td>", escape_html(column)));
+            }
+            table_rows.push_str("</tr>\n");
+        }
+        MockResponse::html(page(&format!(
+            "<a href=\"roster.aspx\">Werk en afwezigheden</a>\n<table><thead><tr><th>Activiteit</th><th>Van</th><th>Plaats</th><th>Tot</th></tr></thead><tbody>\n{table_rows}</tbody></table>"
+        )))
+    }
+}
+
+// The tooltip webcom shows when hovering over a day in the roster, the same one the parser tests use
+pub fn shift_tooltip(date: Date, shift: &MockShift) -> String {
+    TooltipShift {
+        number: &shift.number,
+        start: &shift.start,
+        end: &shift.end,
+        duration: &shift.duration,
+        kind: &shift.kind,
+        location: &shift.location,
+        description: &shift.description,
+    }
+    .tooltip(date)
+}
+
+fn page(content: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Webcom</title></head><body>\n{content}\n</body></html>"
+    )
+}
+
+fn login_page(banner: Option<&str>) -> String {
+    let banner = banner
+        .map(|text| format!("<span id=\"ctl00_lblMessage\">{}</span>", escape_html(text)))
+        .unwrap_or_default();
+    page(&format!(
+        r#"<form method="post" action="./default.aspx" id="aspnetForm">
+<input type="hidden" name="__VIEWSTATE" id="__VIEWSTATE" value="{VIEWSTATE}" />
+<input type="hidden" name="__EVENTVALIDATION" id="__EVENTVALIDATION" value="{EVENT_VALIDATION}" />
+{banner}
+<input name="ctl00$cntMainBody$lgnView$lgnLogin$UserName" type="text" id="ctl00_cntMainBody_lgnView_lgnLogin_UserName" />
+<input name="ctl00$cntMainBody$lgnView$lgnLogin$Password" type="password" id="ctl00_cntMainBody_lgnView_lgnLogin_Password" />
+<input type="submit" name="ctl00$cntMainBody$lgnView$lgnLogin$LoginButton" value="Aanmelden" id="ctl00_cntMainBody_lgnView_lgnLogin_LoginButton" />
+</form>"#
+    ))
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+async fn read_request(stream: &mut TcpStream) -> GenResult<MockRequest> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut request_parts = request_line.split_whitespace();
+    let method = request_parts.next().unwrap_or_default().to_owned();
+    let target = request_parts.next().unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut content_length = 0;
+    let mut cookies = HashMap::new();
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.trim().split_once(':') else {
+            continue;
+        };
+        match name.to_lowercase().as_str() {
+            "content-length" => content_length = value.trim().parse()?,
+            "cookie" => {
+                for cookie in value.split(';') {
+                    if let Some((name, value)) = cookie.trim().split_once('=') {
+                        cookies.insert(name.to_owned(), value.to_owned());
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+    Ok(MockRequest {
+        method,
+        path: path.to_owned(),
+        query: query.to_owned(),
+        cookies,
+        body: String::from_utf8(body)?,
+    })
+}
+
+async fn handle_connection(mut stream: TcpStream, webcom: Arc<MockWebcom>) -> GenResult<()> {
+    let request = read_request(&mut stream).await?;
+    let response = webcom.respond(&request);
+    debug!(
+        "{} {}?{} -> {}",
+        request.method, request.path, request.query, response.status
+    );
+    let mut headers = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        match response.status {
+            200 => "OK",
+            302 => "Found",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Internal Server Error",
+        },
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        headers.push_str(&format!("{name}: {value}\r\n"));
+    }
+    headers.push_str("\r\n");
+    stream.write_all(headers.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+// Serves the mock webcom on an already bound listener until the program is stopped
+pub async fn serve(listener: TcpListener, scenario: MockScenario) -> GenResult<()> {
+    let webcom = Arc::new(MockWebcom::new(scenario));
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let webcom = webcom.clone();
+        tokio::spawn(async move {
+            handle_connection(stream, webcom)
+                .await
+                .warn("Handling mock webcom request");
+        });
+    }
+}
+
+#[cfg(feature = "mock-server")]
+pub async fn run_mock_server(scenario_path: &Path, address: &str) -> GenResult<()> {
+    let scenario = MockScenario::load(scenario_path)?;
+    let listener = TcpListener::bind(address).await?;
+    info!(
+        "Mock webcom running on http://{}, set WEBCOM_URL to this address to use it",
+        listener.local_addr()?
+    );
+    serve(listener, scenario).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        FailureType, SignInFailure,
+        http_client::HttpClient,
+        webcom_client::{CalendarDay, WebcomClient},
+    };
+
+    fn create_scenario() -> MockScenario {
+        MockScenario {
+            username: "12345".to_owned(),
+            password: "geheim".to_owned(),
+            name: "Piet Jansen".to_owned(),
+            months: HashMap::from([(
+                "2025-06".to_owned(),
+                vec![MockShift {
+                    day: 2,
+                    number: "G2309".to_owned(),
+                    start: "06:00".to_owned(),
+                    end: "18:00".to_owned(),
+                    duration: "08:00".to_owned(),
+                    kind: "Rijdienst".to_owned(),
+                    location: "ehvgas, Eindhoven garage streek".to_owned(),
+                    description: "Gebroken".to_owned(),
+                }],
+            )]),
+            days: HashMap::from([(
+                "2025-06".to_owned(),
+                vec![MockDay {
+                    day: 4,
+                    tooltip: "Vrij".to_owned(),
+                }],
+            )]),
+            shift_pages: HashMap::from([(
+                "02-06-2025".to_owned(),
+                vec![vec![
+                    "Rit".to_owned(),
+                    "06:00".to_owned(),
+                    "Eindhoven".to_owned(),
+                    "10:00".to_owned(),
+                ]],
+            )]),
+            ..Default::default()
+        }
+    }
+
+    async fn start_server(scenario: MockScenario) -> HttpClient {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener, scenario));
+        HttpClient::with_url(Some(url)).unwrap()
+    }
+
+    fn sign_in_failure(error: crate::GenError) -> Option<FailureType> {
+        error.failure().cloned()
+    }
+
+    #[tokio::test]
+    async fn http_client_loads_roster() {
+        let mut client = start_server(create_scenario()).await;
+        client.open(0).await.unwrap();
+        client.login("12345", "geheim").await.unwrap();
+        assert_eq!(
+            client.current_user_name().await.unwrap(),
+            Some("Piet".to_owned())
+        );
+        let days = client.fetch_month(2025, Month::June).await.unwrap();
+        assert_eq!(days.len(), 30);
+        let shift_day: &CalendarDay = &days[1];
+        assert_eq!(shift_day.day_text, "2");
+        assert!(
+            shift_day
+                .tooltip
+                .as_ref()
+                .unwrap()
+                .contains("Dienst: G2309")
+        );
+        assert_eq!(days[0].tooltip, Some(String::new()));
+        assert_eq!(days[3].tooltip, Some("Vrij".to_owned()));
+        let date = Date::from_calendar_date(2025, Month::June, 2).unwrap();
+        let rows = client.fetch_shift_detail(date).await.unwrap();
+        assert_eq!(rows, vec![vec!["Rit", "06:00", "Eindhoven", "10:00"]]);
+    }
+
+    #[tokio::test]
+    async fn http_client_sign_in_failures() {
+        let mut client = start_server(create_scenario()).await;
+        client.open(0).await.unwrap();
+        let error = client.login("12345", "fout").await.unwrap_err();
+        assert_eq!(
+            sign_in_failure(error),
+            Some(FailureType::SignInFailed(
+                SignInFailure::IncorrectCredentials
+            ))
+        );
+
+        let mut client = start_server(MockScenario {
+            too_many_tries: true,
+            ..create_scenario()
+        })
+        .await;
+        client.open(0).await.unwrap();
+        let error = client.login("12345", "geheim").await.unwrap_err();
+        assert_eq!(
+            sign_in_failure(error),
+            Some(FailureType::SignInFailed(SignInFailure::TooManyTries))
+        );
+
+        let mut client = start_server(MockScenario {
+            webcom_down: true,
+            ..create_scenario()
+        })
+        .await;
+        client.open(0).await.unwrap();
+        let error = client.login("12345", "geheim").await.unwrap_err();
+        assert_eq!(
+            sign_in_failure(error),
+            Some(FailureType::SignInFailed(SignInFailure::WebcomDown))
+        );
+    }
+
+    #[tokio::test]
+    async fn messages_page_skips_name() {
+        let mut client = start_server(MockScenario {
+            show_messages: true,
+            ..create_scenario()
+        })
+        .await;
+        client.open(0).await.unwrap();
+        client.login("12345", "geheim").await.unwrap();
+        assert_eq!(client.current_user_name().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn messages_are_read_before_roster() {
+        let mut client = start_server(MockScenario {
+            show_messages: true,
+            acknowledge_messages: true,
+            messages: vec![MockMessage {
+                date: "01-06-2025".to_owned(),
+                title: "Omleiding".to_owned(),
+                body: "Lijn 401 rijdt om via de Kennedylaan".to_owned(),
+            }],
+            ..create_scenario()
+        })
+        .await;
+        client.open(0).await.unwrap();
+        client.login("12345", "geheim").await.unwrap();
+        let messages = client.pending_messages().await.unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].title, "Omleiding");
+        assert_eq!(messages[0].body, "Lijn 401 rijdt om via de Kennedylaan");
+        assert!(client.fetch_month(2025, Month::June).await.is_err());
+
+        client.open(0).await.unwrap();
+        client.login("12345", "geheim").await.unwrap();
+        client.acknowledge_messages().await.unwrap();
+        assert!(client.pending_messages().await.unwrap().is_empty());
+        assert_eq!(
+            client.current_user_name().await.unwrap(),
+            Some("Piet".to_owned())
+        );
+        assert_eq!(
+            client.fetch_month(2025, Month::June).await.unwrap().len(),
+            30
+        );
+    }
+
+    #[tokio::test]
+    async fn roster_requires_session() {
+        let mut client = start_server(create_scenario()).await;
+        client.open(0).await.unwrap();
+        assert!(client.fetch_month(2025, Month::June).await.is_err());
+    }
+}
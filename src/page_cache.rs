@@ -0,0 +1,223 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{read_to_string, write},
+};
+
+use serde::{Deserialize, Serialize};
+use time::{Date, Month, Time};
+
+use crate::{
+    GenResult, Shift, create_path,
+    parsing::ParsedMonth,
+    shift::fnv1a,
+    webcom_client::{CalendarDay, ShiftRows},
+};
+
+const CACHE_FILENAME: &str = "page_cache.json";
+// Increase when parsing the roster or shift pages changes, so pages parsed by the old parser are parsed again
+const PARSER_VERSION: u32 = 3;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct CachedMonth {
+    hash: u64,
+    #[serde(flatten)]
+    parsed_month: ParsedMonth,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct CachedShiftPage {
+    hash: u64,
+    broken_period: Vec<(Time, Time)>,
+}
+
+// How many pages did not have to be parsed again during this run
+#[derive(Default, Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct PageCacheStatistics {
+    pub month_hits: u64,
+    pub month_misses: u64,
+    pub shift_page_hits: u64,
+    pub shift_page_misses: u64,
+}
+
+/*
+Keeps a hash of the raw content of every roster month and shift page
+If the content of a page has not changed, the previous result is used instead of parsing the page again
+Broken shifts in a month which has not changed are not loaded again at all
+*/
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct PageCache {
+    // The cache is thrown away when the parser version changes
+    parser_version: u32,
+    months: HashMap<String, CachedMonth>,
+    shift_pages: HashMap<String, CachedShiftPage>,
+    #[serde(skip)]
+    unchanged_months: HashSet<String>,
+    #[serde(skip)]
+    pub statistics: PageCacheStatistics,
+}
+
+impl PageCache {
+    pub fn load() -> Self {
+        let path = create_path(CACHE_FILENAME);
+        match || -> GenResult<Self> { Ok(serde_json::from_str(&read_to_string(path)?)?) }() {
+            Ok(cache) if cache.parser_version == PARSER_VERSION => cache,
+            _ => {
+                info!("No usable page cache found, creating new one");
+                Self {
+                    parser_version: PARSER_VERSION,
+                    ..Default::default()
+                }
+            }
+        }
+    }
+
+    pub fn save(&self) -> GenResult<()> {
+        write(
+            create_path(CACHE_FILENAME),
+            serde_json::to_string(self)?.as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn month_key(year: i32, month: Month) -> String {
+        format!("{year}-{:02}", month as u8)
+    }
+
+    fn shift_key(date: Date) -> String {
+        format!(
+            "{}-{:02}-{:02}",
+            date.year(),
+            date.month() as u8,
+            date.day()
+        )
+    }
+
+    // Hashes the raw calDay content of a roster page
+    pub fn hash_month_page(days: &[CalendarDay]) -> u64 {
+        hash_content(days.iter().map(|day| {
+            vec![
+                day.tooltip.as_deref().unwrap_or_default(),
+                day.day_text.as_str(),
+            ]
+        }))
+    }
+
+    // Hashes the trip table of a shift page
+    pub fn hash_shift_page(rows: &ShiftRows) -> u64 {
+        hash_content(
+            rows.iter()
+                .map(|row| row.iter().map(String::as_str).collect()),
+        )
+    }
+
+    // Returns the previously parsed shifts and failed shifts of a month, if its content has not changed
+    pub fn get_month(&mut self, year: i32, month: Month, hash: u64) -> Option<ParsedMonth> {
+        let key = Self::month_key(year, month);
+        match self.months.get(&key) {
+            Some(cached) if cached.hash == hash => {
+                self.statistics.month_hits += 1;
+                self.unchanged_months.insert(key);
+                Some(cached.parsed_month.clone())
+            }
+            _ => {
+                self.statistics.month_misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn store_month(&mut self, year: i32, month: Month, hash: u64, parsed_month: &ParsedMonth) {
+        self.months.insert(
+            Self::month_key(year, month),
+            CachedMonth {
+                hash,
+                parsed_month: parsed_month.clone(),
+            },
+        );
+    }
+
+    // If the month of the shift has not changed, the broken periods found last time can be used without loading the page
+    pub fn get_unchanged_broken_period(&mut self, shift: &Shift) -> Option<Vec<(Time, Time)>> {
+        let key = Self::month_key(shift.date.year(), shift.date.month());
+        if !self.unchanged_months.contains(&key) {
+            return None;
+        }
+        let cached = self.shift_pages.get(&Self::shift_key(shift.date))?;
+        self.statistics.shift_page_hits += 1;
+        Some(cached.broken_period.clone())
+    }
+
+    // Returns the previously found broken periods, if the shift page has not changed
+    pub fn get_shift_page(&mut self, date: Date, hash: u64) -> Option<Vec<(Time, Time)>> {
+        match self.shift_pages.get(&Self::shift_key(date)) {
+            Some(cached) if cached.hash == hash => {
+                self.statistics.shift_page_hits += 1;
+                Some(cached.broken_period.clone())
+            }
+            _ => {
+                self.statistics.shift_page_misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn store_shift_page(&mut self, date: Date, hash: u64, broken_period: &[(Time, Time)]) {
+        self.shift_pages.insert(
+            Self::shift_key(date),
+            CachedShiftPage {
+                hash,
+                broken_period: broken_period.to_vec(),
+            },
+        );
+    }
+}
+
+/*
+Hashes groups of texts, like the columns of a row, with the same FNV-1a hash as the shift id so the hash stays the same across Rust versions
+Every text ends with 0xff and every group with 0xfe, which are never in UTF-8, so moving text between columns changes the hash
+*/
+fn hash_content<'a>(groups: impl Iterator<Item = Vec<&'a str>>) -> u64 {
+    let mut bytes = vec![];
+    for group in groups {
+        for text in group {
+            bytes.extend_from_slice(text.as_bytes());
+            bytes.push(0xff);
+        }
+        bytes.push(0xfe);
+    }
+    fnv1a(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn month_is_only_reused_with_same_hash() {
+        let mut cache = PageCache::default();
+        let parsed_month = ParsedMonth {
+            failed_shifts: 1,
+            ..Default::default()
+        };
+        cache.store_month(2025, Month::March, 42, &parsed_month);
+        assert!(cache.get_month(2025, Month::March, 43).is_none());
+        assert_eq!(cache.get_month(2025, Month::March, 42), Some(parsed_month));
+        assert_eq!(cache.statistics.month_hits, 1);
+        assert_eq!(cache.statistics.month_misses, 1);
+    }
+
+    // The hash is stored, so it must stay the same after a Rust upgrade
+    #[test]
+    fn page_hash_is_stable() {
+        let days = [CalendarDay {
+            tooltip: Some("V1".to_owned()),
+            day_text: "2".to_owned(),
+        }];
+        assert_eq!(PageCache::hash_month_page(&days), 0x054f_a81d_b977_6f70);
+        let rows = vec![vec!["V1".to_owned()], vec!["2".to_owned()]];
+        assert_ne!(
+            PageCache::hash_shift_page(&rows),
+            PageCache::hash_shift_page(&vec![vec!["V1".to_owned(), "2".to_owned()]])
+        );
+    }
+}
@@ -1,196 +1,310 @@
+use crate::absence::{Absence, AbsenceStore};
 use crate::email::DATE_DESCRIPTION;
-use crate::errors::{OptionResult, check_if_webcom_unavailable, check_sign_in_error};
-use crate::gebroken_shifts::{navigate_to_subdirectory, wait_for_response};
+use crate::errors::{OptionResult, ResultLog, WebcomError};
 use crate::health::ApplicationLogbook;
-use crate::{FailureType, GenResult, Shift, set_get_name, wait_until_loaded};
-use async_recursion::async_recursion;
-use thirtyfour::prelude::ElementQueryable;
-use thirtyfour::{By, WebDriver};
+use crate::logging::{self, Phase};
+use crate::messages::handle_messages;
+use crate::page_cache::PageCache;
+use crate::rate_limit;
+use crate::webcom_client::{CalendarDay, WebcomClient};
+use crate::{GenError, GenResult, Shift, set_get_name};
+use dotenvy::var;
+use serde::{Deserialize, Serialize};
 use time::{Date, Month};
 
+// Everything found in the roster of a single month
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ParsedMonth {
+    pub shifts: Vec<Shift>,
+    pub absences: Vec<Absence>,
+    // The amount of shifts which could not be parsed
+    pub failed_shifts: u64,
+}
+
 /*
-Checks all supplied WebElements, it checks if the day contains the text "Dienstuur"  and if so, adds it to a Vec of valid shifts in the calendar
-Does not search itself for elements
+Checks all days in the roster, if the day contains the text "Dienstuur" it is added to a Vec of valid shifts in the calendar
+Every other day with a tooltip is a day off, leave or another absence
 */
-async fn get_elements(driver: &WebDriver, month: Month, year: i32) -> GenResult<(Vec<Shift>, u64)> {
-    let mut temp_emlements: Vec<Shift> = vec![];
-    let mut failed_shifts = 0;
-    let elements = driver
-        .query(By::ClassName("calDay"))
-        .all_from_selector()
-        .await?;
-    for element in elements {
-        let text = match element.attr("data-original-title").await? {
-            Some(x) => x,
+fn parse_calendar_days(days: &[CalendarDay], month: Month, year: i32) -> GenResult<ParsedMonth> {
+    let mut parsed_month = ParsedMonth::default();
+    for day in days {
+        let text = match &day.tooltip {
+            Some(x) => x.clone(),
             None => {
-                return Err("no elements in rooster".into());
+                return Err(WebcomError::scraping("no elements in rooster"));
             }
         };
-        if !text.is_empty() && text.contains("Dienstduur") {
-            //debug!("Loading shift: {:?}", &text);
-            let dag_text = element.find(By::Tag("strong")).await?.text().await?;
-            let dag_text_split = dag_text.split_whitespace().next().result()?;
-
-            debug!("dag {}", &dag_text_split);
-            let dag: u8 = dag_text_split.parse()?;
-            let date = Date::from_calendar_date(year, month, dag)?;
-            let new_shift = Shift::new(text, date);
-            match new_shift {
-                Ok(shift) => {
-                    temp_emlements.push(shift.clone());
-                    debug!("Found Shift {}", &shift.number);
-                }
+        if text.trim().is_empty() {
+            continue;
+        }
+        if !text.contains("Dienstduur") {
+            // Absences are not important enough to fail the month for, so unreadable days are skipped
+            let date = match day_date(day, month, year) {
+                Ok(date) => date,
                 Err(error) => {
-                    error!(
-                        "FAILED TO CREATE SHIFT!\nDATE: {}\nERROR: {}",
-                        date.format(DATE_DESCRIPTION)?,
-                        error.to_string()
+                    warn!(
+                        "Skipping absence, day {:?} can not be read: {error}",
+                        day.day_text
                     );
-                    failed_shifts += 1;
+                    continue;
                 }
+            };
+            if let Some(absence) = Absence::new(&text, date) {
+                debug!("Found {} on {}", absence.status.summary(), date);
+                parsed_month.absences.push(absence);
+            }
+            continue;
+        }
+        let date = day_date(day, month, year)?;
+        //debug!("Loading shift: {:?}", &text);
+        let new_shift = Shift::new(text.clone(), date).map_err(|error| error.with_text(&text));
+        match new_shift {
+            Ok(shift) => {
+                debug!("Found Shift {}", &shift.number);
+                parsed_month.shifts.push(shift);
+            }
+            Err(error) => {
+                error!(
+                    "FAILED TO CREATE SHIFT!\nDATE: {}\nERROR: {}",
+                    date.format(DATE_DESCRIPTION)?,
+                    error
+                );
+                parsed_month.failed_shifts += 1;
             }
         }
     }
-    Ok((temp_emlements, failed_shifts))
+    Ok(parsed_month)
 }
 
-/*
-Just presses the previous button in webcom to load the previous month
-*/
-#[async_recursion]
-pub async fn load_previous_month_shifts(
-    driver: &WebDriver,
-    extra_months_back: usize,
-) -> GenResult<Vec<Shift>> {
-    debug!("Loading Previous Month..");
-    let now = time::OffsetDateTime::now_utc();
-    let today = now.date();
-    let mut new_month = today.month().previous();
-    let mut new_year = if new_month == Month::December {
-        today.year() - 1
-    } else {
-        today.year()
-    };
-    for _ in 0..extra_months_back {
-        info!("Going way back");
-        new_month = new_month.previous();
+// The date of a day in the roster, read from the day of month shown in the cell
+fn day_date(day: &CalendarDay, month: Month, year: i32) -> GenResult<Date> {
+    let dag_text_split = day
+        .day_text
+        .split_whitespace()
+        .next()
+        .result("day of month")?;
+    debug!("dag {}", &dag_text_split);
+    let dag: u8 = dag_text_split
+        .parse()
+        .map_err(|error| WebcomError::from(error).with_text(&day.day_text))?;
+    Ok(Date::from_calendar_date(year, month, dag)?)
+}
 
-        new_year = if new_month == Month::December {
-            new_year - 1
-        } else {
-            new_year
+// Which months should be loaded, relative to the current month
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonthRange {
+    pub months_back: u32,
+    pub months_ahead: u32,
+    // Stop going further back or ahead as soon as a month without shifts is found
+    pub stop_at_empty_month: bool,
+}
+
+impl MonthRange {
+    /*
+    Loads the range from the env, defaults to one month back and one ahead
+    Webcom often publishes rosters a few months ahead, so MONTHS_AHEAD can be increased
+    */
+    pub fn from_env() -> Self {
+        let read_number = |name: &str| -> u32 {
+            var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(1)
         };
+        Self {
+            months_back: read_number("MONTHS_BACK"),
+            months_ahead: read_number("MONTHS_AHEAD"),
+            stop_at_empty_month: var("STOP_AT_EMPTY_MONTH").unwrap_or_default() == "true",
+        }
     }
-    let mut shifts = vec![];
-    if extra_months_back > 0 {
-        shifts.append(&mut load_previous_month_shifts(driver, extra_months_back - 1).await?)
-    }
-    navigate_to_subdirectory(
-        &driver,
-        &format!("roster.aspx?{}-{}-01", new_year, new_month as u8),
-    )
-    .await?;
-    wait_until_loaded(&driver).await?;
-    shifts.append(&mut get_elements(&driver, new_month, new_year).await?.0);
-    Ok(shifts)
+}
+
+// Returns the year and month which is offset months away from the given date
+pub fn offset_month(date: Date, offset: i32) -> (i32, Month) {
+    let month_index = date.year() * 12 + date.month() as i32 - 1 + offset;
+    let year = month_index.div_euclid(12);
+    let month = Month::January.nth_next(month_index.rem_euclid(12) as u8);
+    (year, month)
 }
 
 /*
-Just presses the next button in webcom twice to load the next month.
-Only works correctly if the previous month function has been ran before
+Loads all shifts in the month range
+The current month is loaded first
+Then it walks back month by month, and then ahead month by month
+Shifts and failed shifts of every month are written to the logbook, absences to the absence store
+Stops at the first month which fails
 */
-pub async fn load_next_month_shifts(
-    driver: &WebDriver,
+pub async fn load_month_range_shifts(
+    client: &mut impl WebcomClient,
     logbook: &mut ApplicationLogbook,
+    page_cache: &mut PageCache,
+    absences: &mut AbsenceStore,
+    range: &MonthRange,
 ) -> GenResult<Vec<Shift>> {
-    debug!("Loading Next Month..");
-    let now = time::OffsetDateTime::now_utc();
-    let today = now.date();
-    let new_month = today.month().next();
-    let new_year = if new_month == Month::January {
-        today.year() + 1
-    } else {
-        today.year()
-    };
-    navigate_to_subdirectory(
-        &driver,
-        &format!("roster.aspx?{}-{}-01", new_year, new_month as u8),
-    )
-    .await?;
-    wait_until_loaded(&driver).await?;
-    let shifts = get_elements(&driver, new_month, new_year).await?;
-    logbook.add_failed_shifts(shifts.1, false);
-    Ok(shifts.0)
+    logging::set_phase(Phase::FetchMonth);
+    let today = time::OffsetDateTime::now_utc().date();
+    logbook.clear_month_statistics();
+    let mut shifts = vec![];
+    for offsets in [
+        vec![0],
+        (1..=range.months_back as i32)
+            .map(|offset| -offset)
+            .collect::<Vec<i32>>(),
+        (1..=range.months_ahead as i32).collect::<Vec<i32>>(),
+    ] {
+        for offset in offsets {
+            let (year, month) = offset_month(today, offset);
+            // The failed month is already written to the logbook, and the run is tried again from the start
+            // So the other months are not loaded, that would only be loading pages which are thrown away
+            let mut month_shifts =
+                load_month_shifts(client, logbook, page_cache, absences, year, month)
+                    .await
+                    .inspect_err(|error| {
+                        warn!("Loading month {year}-{} failed: {error}", month as u8)
+                    })?;
+            let month_empty = month_shifts.is_empty();
+            shifts.append(&mut month_shifts);
+            if month_empty && range.stop_at_empty_month && offset != 0 {
+                info!("No shifts found {offset} months away, not loading further");
+                break;
+            }
+        }
+    }
+    Ok(shifts)
 }
 
-pub async fn load_current_month_shifts(
-    driver: &WebDriver,
+/*
+Load a single month
+The month is only parsed if its content has changed since the last time
+The absences of the month replace the stored absences of that month
+*/
+pub async fn load_month_shifts(
+    client: &mut impl WebcomClient,
     logbook: &mut ApplicationLogbook,
+    page_cache: &mut PageCache,
+    absences: &mut AbsenceStore,
+    year: i32,
+    month: Month,
 ) -> GenResult<Vec<Shift>> {
-    let now = time::OffsetDateTime::now_utc();
-    let today = now.date();
-    let shifts = get_elements(&driver, today.month(), today.year()).await?;
-    logbook.add_failed_shifts(shifts.1, false);
-    Ok(shifts.0)
+    debug!("Loading month {year}-{}", month as u8);
+    let result = async {
+        let days = client.fetch_month(year, month).await?;
+        let page_hash = PageCache::hash_month_page(&days);
+        if let Some(cached_month) = page_cache.get_month(year, month, page_hash) {
+            debug!("Month {year}-{} has not changed", month as u8);
+            return Ok(cached_month);
+        }
+        let parsed_month = parse_calendar_days(&days, month, year)?;
+        page_cache.store_month(year, month, page_hash, &parsed_month);
+        Ok::<ParsedMonth, GenError>(parsed_month)
+    }
+    .await;
+    match result {
+        Ok(parsed_month) => {
+            let failed_shifts = parsed_month.failed_shifts;
+            logbook.add_failed_shifts(failed_shifts, false);
+            logbook.add_month_statistics(
+                year,
+                month,
+                parsed_month.shifts.len() as u64,
+                failed_shifts,
+                None,
+            );
+            absences.replace_month(year, month, &parsed_month.absences);
+            Ok(parsed_month.shifts)
+        }
+        Err(err) => {
+            logbook.add_month_statistics(year, month, 0, 0, Some(err.to_string()));
+            Err(err)
+        }
+    }
 }
 
 /*
 Logs into webcom, has no logic for when the login fails.
-It will also find and return the first name of the user, this will fail if the login is unsuccesful
+It will also find and save the first name of the user
 */
-pub async fn load_calendar(driver: &WebDriver, user: &str, pass: &str) -> GenResult<()> {
+pub async fn load_calendar(
+    client: &mut impl WebcomClient,
+    user: &str,
+    pass: &str,
+) -> GenResult<()> {
+    logging::set_phase(Phase::Login);
+    rate_limit::wait_for_login(user).await;
     info!("Logging in..");
-    sign_in_webcom(driver, user, pass).await?;
-    info!("Loading rooster..");
-    navigate_to_subdirectory(driver, "roster.aspx").await?;
+    client.login(user, pass).await?;
+    // Webcom can show messages after signing in, which have to be read before the roster can be opened
+    let messages = client
+        .pending_messages()
+        .await
+        .warn_owned("Reading webcom messages")
+        .unwrap_or_default();
+    if !messages.is_empty() {
+        handle_messages(&messages).warn("Handling webcom messages");
+    }
+    // The messages page is not understood well enough to stop the run on it, a roster which does not open fails later anyway
+    client
+        .acknowledge_messages()
+        .await
+        .warn("Acknowledging webcom messages");
+    if let Some(name) = client.current_user_name().await? {
+        set_get_name(Some(name));
+    }
     Ok(())
 }
 
-async fn sign_in_webcom(driver: &WebDriver, user: &str, pass: &str) -> GenResult<()> {
-    let possible_error = match driver.find(By::Id("h3")).await {
-        Ok(element) => Some(element.text().await.unwrap_or("GEEN TEKST".to_owned())),
-        Err(_) => None,
-    };
-    let username_field = driver
-        .find(By::Id("ctl00_cntMainBody_lgnView_lgnLogin_UserName"))
-        .await
-        .map_err(|error| match check_if_webcom_unavailable(possible_error) {
-            true => Box::new(FailureType::SignInFailed(crate::SignInFailure::WebcomDown)),
-            false => Box::new(FailureType::Other(error.to_string())),
-        })?;
-    username_field.send_keys(user).await?;
-    let password_field = driver
-        .find(By::Id("ctl00_cntMainBody_lgnView_lgnLogin_Password"))
-        .await?;
-    password_field.send_keys(pass).await?;
-    driver
-        .find(By::Id("ctl00_cntMainBody_lgnView_lgnLogin_LoginButton"))
-        .await?
-        .click()
-        .await?;
-    debug!("waiting until login page is loaded");
-    let _ = wait_for_response(&driver, By::Tag("h3"), false).await;
-    debug!("login page is loaded");
-    if let Ok(url) = driver.current_url().await
-        && url.path() == "/WebComm/messages.aspx"
-    {
-        info!("Got redirected to message, wont try to get name");
-    } else {
-        let name_text = match driver.find(By::Tag("h3")).await {
-            Ok(element) => element.text().await?,
-            Err(_) => {
-                return Err(Box::new(check_sign_in_error(driver).await?));
-            }
+// Only the first word after the last comma of the welcome header is kept, this is the first name
+pub fn parse_user_name(welcome_text: &str) -> GenResult<String> {
+    Ok(welcome_text
+        .split(",")
+        .last()
+        .result("user name")?
+        .split_whitespace()
+        .next()
+        .result("first name")?
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shift::test_tooltip;
+
+    #[test]
+    fn offset_month_rolls_over_years() {
+        let date = Date::from_calendar_date(2025, Month::January, 15).unwrap();
+        assert_eq!(offset_month(date, 0), (2025, Month::January));
+        assert_eq!(offset_month(date, -1), (2024, Month::December));
+        assert_eq!(offset_month(date, -13), (2023, Month::December));
+        assert_eq!(offset_month(date, 11), (2025, Month::December));
+        assert_eq!(offset_month(date, 12), (2026, Month::January));
+    }
+
+    #[test]
+    fn unreadable_absence_days_are_skipped() {
+        let date = Date::from_calendar_date(2025, Month::June, 2).unwrap();
+        let day = |tooltip: String, day_text: &str| CalendarDay {
+            tooltip: Some(tooltip),
+            day_text: day_text.to_owned(),
         };
-        let name = name_text
-            .split(",")
-            .last()
-            .result()?
-            .split_whitespace()
-            .next()
-            .result()?
-            .to_string();
-        set_get_name(Some(name));
+        let days = [
+            day("Vrij".to_owned(), ""),
+            day("Verlof".to_owned(), "31"),
+            day(test_tooltip(date, "V1", "06:00", "14:00"), "2 ma"),
+        ];
+        let parsed_month = parse_calendar_days(&days, Month::June, 2025).unwrap();
+        assert_eq!(parsed_month.shifts.len(), 1);
+        assert!(parsed_month.absences.is_empty());
+
+        let unreadable_shift = day(test_tooltip(date, "V1", "06:00", "14:00"), "");
+        assert!(parse_calendar_days(&[unreadable_shift], Month::June, 2025).is_err());
+    }
+
+    #[test]
+    fn user_name_is_first_name() {
+        assert_eq!(
+            parse_user_name("Goedemorgen, Piet Jan").unwrap(),
+            "Piet".to_owned()
+        );
     }
-    Ok(())
 }
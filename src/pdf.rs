@@ -0,0 +1,315 @@
+use std::{fs::write, path::PathBuf};
+
+use dotenvy::var;
+use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref, Str};
+use time::{Date, Duration, Month};
+
+use crate::{
+    GenResult, Shift, create_ical_filename, email::TIME_DESCRIPTION, errors::ResultLog,
+    parsing::offset_month, set_get_name,
+};
+
+const PAGE_WIDTH: f32 = 842.0;
+const PAGE_HEIGHT: f32 = 595.0;
+const MARGIN: f32 = 30.0;
+const HEADER_HEIGHT: f32 = 18.0;
+const GRID_TOP: f32 = PAGE_HEIGHT - 65.0;
+const LINE_HEIGHT: f32 = 9.5;
+const TEXT_SIZE: f32 = 8.0;
+
+const FONT_REGULAR: Name = Name(b"F1");
+const FONT_BOLD: Name = Name(b"F2");
+
+const WEEKDAYS: [&str; 7] = [
+    "Maandag",
+    "Dinsdag",
+    "Woensdag",
+    "Donderdag",
+    "Vrijdag",
+    "Zaterdag",
+    "Zondag",
+];
+const MONTHS: [&str; 12] = [
+    "januari",
+    "februari",
+    "maart",
+    "april",
+    "mei",
+    "juni",
+    "juli",
+    "augustus",
+    "september",
+    "oktober",
+    "november",
+    "december",
+];
+
+// A single line of text in a day cell
+struct CellLine {
+    text: String,
+    bold: bool,
+    color: (f32, f32, f32),
+}
+
+impl CellLine {
+    fn new(text: String) -> Self {
+        Self {
+            text,
+            bold: false,
+            color: (0.0, 0.0, 0.0),
+        }
+    }
+}
+
+/*
+Creates a printable month grid of all shifts which start in the given month
+Uses the fonts built into every PDF reader, so nothing needs to be embedded
+*/
+pub fn create_month_pdf(shifts: &[Shift], year: i32, month: Month) -> GenResult<Vec<u8>> {
+    let first_day = Date::from_calendar_date(year, month, 1)?;
+    let days_in_month = time::util::days_in_month(month, year);
+    let weekday_offset = first_day.weekday().number_days_from_monday() as u32;
+    let weeks = (weekday_offset + days_in_month as u32).div_ceil(7);
+    let column_width = (PAGE_WIDTH - 2.0 * MARGIN) / 7.0;
+    let row_height = (GRID_TOP - HEADER_HEIGHT - MARGIN) / weeks as f32;
+
+    let mut content = Content::new();
+    content.set_line_width(0.5);
+    content.set_stroke_gray(0.4);
+
+    let title = format!(
+        "Rooster {} - {} {year}",
+        set_get_name(None),
+        MONTHS[month as usize - 1]
+    );
+    write_text(
+        &mut content,
+        FONT_BOLD,
+        16.0,
+        MARGIN,
+        PAGE_HEIGHT - 45.0,
+        &title,
+    );
+
+    for (column, weekday) in WEEKDAYS.iter().enumerate() {
+        let x = MARGIN + column as f32 * column_width;
+        content.set_fill_gray(0.85);
+        content
+            .rect(x, GRID_TOP - HEADER_HEIGHT, column_width, HEADER_HEIGHT)
+            .fill_nonzero_and_stroke();
+        content.set_fill_gray(0.0);
+        write_text(
+            &mut content,
+            FONT_BOLD,
+            9.0,
+            x + 4.0,
+            GRID_TOP - 13.0,
+            weekday,
+        );
+    }
+
+    for cell in 0..weeks * 7 {
+        let x = MARGIN + (cell % 7) as f32 * column_width;
+        let y = GRID_TOP - HEADER_HEIGHT - (cell / 7 + 1) as f32 * row_height;
+        let day_number = cell as i64 - weekday_offset as i64;
+        let date = first_day + Duration::days(day_number);
+        // Days outside of this month and weekends are shaded
+        let shade = match date.month() == month {
+            false => 0.9,
+            true if cell % 7 >= 5 => 0.96,
+            true => 1.0,
+        };
+        content.set_fill_gray(shade);
+        content
+            .rect(x, y, column_width, row_height)
+            .fill_nonzero_and_stroke();
+        if date.month() != month {
+            continue;
+        }
+        let max_lines = ((row_height - 6.0) / LINE_HEIGHT) as usize;
+        let mut text_y = y + row_height - 11.0;
+        for line in create_day_lines(shifts, date).iter().take(max_lines) {
+            let (red, green, blue) = line.color;
+            content.set_fill_rgb(red, green, blue);
+            let font = if line.bold { FONT_BOLD } else { FONT_REGULAR };
+            let text = truncate_to_width(&line.text, column_width - 8.0);
+            write_text(&mut content, font, TEXT_SIZE, x + 4.0, text_y, &text);
+            text_y -= LINE_HEIGHT;
+        }
+    }
+
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    let page_id = Ref::new(3);
+    let font_regular_id = Ref::new(4);
+    let font_bold_id = Ref::new(5);
+    let content_id = Ref::new(6);
+
+    let mut pdf = Pdf::new();
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.pages(page_tree_id).kids([page_id]).count(1);
+    let mut page = pdf.page(page_id);
+    page.media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT));
+    page.parent(page_tree_id);
+    page.contents(content_id);
+    page.resources()
+        .fonts()
+        .pair(FONT_REGULAR, font_regular_id)
+        .pair(FONT_BOLD, font_bold_id);
+    page.finish();
+    pdf.type1_font(font_regular_id)
+        .base_font(Name(b"Helvetica"))
+        .encoding_predefined(Name(b"WinAnsiEncoding"));
+    pdf.type1_font(font_bold_id)
+        .base_font(Name(b"Helvetica-Bold"))
+        .encoding_predefined(Name(b"WinAnsiEncoding"));
+    pdf.stream(content_id, &content.finish());
+    Ok(pdf.finish())
+}
+
+// All lines shown in the cell of a single day
+fn create_day_lines(shifts: &[Shift], date: Date) -> Vec<CellLine> {
+    let mut lines = vec![CellLine {
+        text: date.day().to_string(),
+        bold: true,
+        color: (0.0, 0.0, 0.0),
+    }];
+    if let Some(holiday) = crate::holidays::holiday_on(date) {
+        lines.push(CellLine {
+            text: holiday.to_string(),
+            bold: false,
+            color: (0.65, 0.11, 0.18),
+        });
+    }
+    for shift in shifts.iter().filter(|shift| shift.date == date) {
+        lines.push(CellLine {
+            text: format!(
+                "{}  {} - {}",
+                shift.number,
+                shift.start.format(TIME_DESCRIPTION).unwrap_or_default(),
+                shift.end.format(TIME_DESCRIPTION).unwrap_or_default()
+            ),
+            bold: true,
+            color: (0.1, 0.37, 0.71),
+        });
+        for period in shift.broken_period.iter().flatten() {
+            lines.push(CellLine::new(format!(
+                "Pauze {} - {}",
+                period.0.format(TIME_DESCRIPTION).unwrap_or_default(),
+                period.1.format(TIME_DESCRIPTION).unwrap_or_default()
+            )));
+        }
+        if !shift.kind.is_empty() {
+            lines.push(CellLine::new(shift.kind.clone()));
+        }
+        if !shift.description.is_empty() {
+            lines.push(CellLine::new(shift.description.clone()));
+        }
+    }
+    lines
+}
+
+fn write_text(content: &mut Content, font: Name, size: f32, x: f32, y: f32, text: &str) {
+    let encoded = encode_win_ansi(text);
+    content.begin_text();
+    content.set_font(font, size);
+    content.next_line(x, y);
+    content.show(Str(&encoded));
+    content.end_text();
+}
+
+// The built-in fonts only know the WinAnsi characters, everything else is replaced
+fn encode_win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|character| match character as u32 {
+            0x20..=0x7e | 0xa0..=0xff => character as u8,
+            _ => b'?',
+        })
+        .collect()
+}
+
+// Helvetica is roughly half as wide as it is high, good enough to keep text inside its cell
+fn truncate_to_width(text: &str, width: f32) -> String {
+    let max_characters = (width / (TEXT_SIZE * 0.5)) as usize;
+    match text.chars().count() > max_characters {
+        true => format!(
+            "{}..",
+            text.chars()
+                .take(max_characters.saturating_sub(2))
+                .collect::<String>()
+        ),
+        false => text.to_owned(),
+    }
+}
+
+// The PDF is saved next to the calendar, so it can be served by the same web server
+pub fn create_pdf_filename(year: i32, month: Month) -> GenResult<String> {
+    let ical_filename = create_ical_filename()?;
+    let stem = ical_filename.trim_end_matches(".ics");
+    Ok(format!("{stem}_{year}-{:02}.pdf", month as u8))
+}
+
+/*
+Saves a printable roster of this and next month next to the calendar file
+Only runs if SAVE_PDF_ROSTER is true
+*/
+pub fn save_month_pdfs(shifts: &[Shift]) -> GenResult<()> {
+    if var("SAVE_PDF_ROSTER").unwrap_or_default() != "true" {
+        return Ok(());
+    }
+    let today = time::OffsetDateTime::now_utc().date();
+    for (year, month) in [offset_month(today, 0), offset_month(today, 1)] {
+        let mut path = PathBuf::from(var("SAVE_TARGET")?);
+        path.push(create_pdf_filename(year, month)?);
+        debug!("Saving PDF roster to {:?}", &path);
+        write(path, create_month_pdf(shifts, year, month)?).warn("Saving PDF roster");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::time;
+
+    #[test]
+    fn month_pdf_is_a_pdf() {
+        let date = Date::from_calendar_date(2025, Month::June, 2).unwrap();
+        let shifts = vec![Shift::test_shift(date, "V1")];
+        let pdf = create_month_pdf(&shifts, 2025, Month::June).unwrap();
+        assert!(pdf.starts_with(b"%PDF"));
+    }
+
+    #[test]
+    fn day_lines_contain_holiday_times_and_breaks() {
+        let christmas = Date::from_calendar_date(2025, Month::December, 25).unwrap();
+        let mut shift = Shift::test_shift(christmas, "G201");
+        shift.broken_period = Some(vec![(time!(09:30), time!(11:00))]);
+        let other_day = Shift::test_shift(christmas + Duration::days(1), "V2");
+        let lines: Vec<String> = create_day_lines(&[shift, other_day], christmas)
+            .into_iter()
+            .map(|line| line.text)
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "25",
+                "Eerste Kerstdag",
+                "G201  06:00 - 14:00",
+                "Pauze 09:30 - 11:00",
+                "Rijdienst",
+                "Lijn 401",
+            ]
+        );
+    }
+
+    #[test]
+    fn text_is_encoded_and_truncated() {
+        assert_eq!(encode_win_ansi("Café"), b"Caf\xe9");
+        assert_eq!(encode_win_ansi("€ 5 → 6"), b"? 5 ? 6");
+        assert_eq!(truncate_to_width("Lijn 401", 100.0), "Lijn 401");
+        let truncated = truncate_to_width(&"Eindhoven ".repeat(10), 40.0);
+        assert_eq!(truncated, "Eindhove..");
+        assert_eq!(truncate_to_width("Zoë", 4.0), "..");
+    }
+}
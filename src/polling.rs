@@ -0,0 +1,249 @@
+use std::{
+    collections::HashSet,
+    fs::{read_to_string, write},
+    time::Duration,
+};
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use dotenvy::var;
+use serde::{Deserialize, Serialize};
+use time::{Date, Month};
+
+use crate::{
+    FailureType, GenResult, Shift, create_path,
+    errors::SignInFailure,
+    health::ApplicationLogbook,
+    ical::load_stored_shifts,
+    schedule::{HEARTBEAT_MARGIN_SECONDS, kuma_heartbeat_interval},
+};
+
+const CHANGE_HISTORY_FILENAME: &str = "change_history.json";
+// Detections older than this are forgotten, so the history follows when planners publish now
+const CHANGE_HISTORY_DAYS: i64 = 365;
+// On how many different days changes must have been found around the same day of the month and hour
+const MIN_DETECTION_DAYS: usize = 2;
+// The longest wait when backing off, webcom is not checked less often than this
+const MAX_BACKOFF: Duration = Duration::from_secs(8 * 3600);
+
+// When a run found changes to the roster, in local time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChangeDetection {
+    pub date: Date,
+    pub hour: u8,
+}
+
+/*
+When changes to the roster have been found before
+Planners often publish the next month around the same day of the month, so these days are checked more often
+*/
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ChangeHistory {
+    detections: Vec<ChangeDetection>,
+}
+
+impl ChangeHistory {
+    pub fn load() -> Self {
+        match read_to_string(create_path(CHANGE_HISTORY_FILENAME)) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|error| {
+                warn!("Change history could not be read, starting over. Error: {error}");
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self) -> GenResult<()> {
+        write(
+            create_path(CHANGE_HISTORY_FILENAME),
+            serde_json::to_string_pretty(self)?.as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    // Remembers that changes were found at this moment, and forgets old detections
+    pub fn record(&mut self, now: NaiveDateTime) -> GenResult<()> {
+        let date = to_date(now.date())?;
+        self.detections.retain(|detection| {
+            (date - detection.date).whole_days() <= CHANGE_HISTORY_DAYS
+                && !(detection.date == date && detection.hour == now.hour() as u8)
+        });
+        self.detections.push(ChangeDetection {
+            date,
+            hour: now.hour() as u8,
+        });
+        Ok(())
+    }
+
+    /*
+    If changes have been found around this day of the month and hour on enough different days
+    A day before or after counts as well, as planners do not always publish on the same day
+    So changes found on the 31st count for the 1st of the next month
+    */
+    pub fn is_busy_time(&self, now: NaiveDateTime) -> bool {
+        let day = now.day();
+        let busy_days = self
+            .detections
+            .iter()
+            .filter(|detection| {
+                detection.hour as u32 == now.hour()
+                    && [
+                        detection.date.previous_day(),
+                        Some(detection.date),
+                        detection.date.next_day(),
+                    ]
+                    .into_iter()
+                    .flatten()
+                    .any(|date| date.day() as u32 == day)
+            })
+            .map(|detection| detection.date)
+            .collect::<HashSet<Date>>()
+            .len();
+        busy_days >= MIN_DETECTION_DAYS
+    }
+}
+
+// How often webcom should be checked for now
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PollingMode {
+    Normal,
+    // Changes are likely, check every ADAPTIVE_INTERVAL minutes
+    Fast,
+    // Webcom refused or is down, wait longer the more runs in a row failed
+    Backoff(u64),
+}
+
+impl PollingMode {
+    pub fn decide(
+        history: &ChangeHistory,
+        now: NaiveDateTime,
+        shift_tomorrow: bool,
+        logbook: &ApplicationLogbook,
+    ) -> Self {
+        match &logbook.state {
+            FailureType::SignInFailed(SignInFailure::TooManyTries | SignInFailure::WebcomDown) => {
+                Self::Backoff(logbook.repeat_count + 1)
+            }
+            _ if shift_tomorrow || history.is_busy_time(now) => Self::Fast,
+            _ => Self::Normal,
+        }
+    }
+
+    /*
+    The wait until the next run, normal_wait is the wait the schedule or CYCLE_TIME gives
+    Backing off doubles the normal wait for every failed run, up to max_backoff
+    */
+    pub fn wait(&self, normal_wait: Duration, max_backoff: Duration) -> Duration {
+        match self {
+            Self::Normal => normal_wait,
+            Self::Fast => normal_wait.min(fast_interval()),
+            Self::Backoff(failed_runs) => normal_wait
+                .saturating_mul(2u32.saturating_pow(*failed_runs as u32))
+                .min(max_backoff)
+                .max(normal_wait),
+        }
+    }
+}
+
+/*
+The longest wait when backing off
+Kuma marks the user as down when there is no run within its heartbeat interval, so the wait stays within it
+*/
+pub fn max_backoff() -> Duration {
+    kuma_heartbeat_interval()
+        .ok()
+        .map(|interval| (interval as i64 - HEARTBEAT_MARGIN_SECONDS).max(0) as u64)
+        .map_or(MAX_BACKOFF, |seconds| {
+            MAX_BACKOFF.min(Duration::from_secs(seconds))
+        })
+}
+
+// Set with ADAPTIVE_POLLING=true
+pub fn adaptive_polling_enabled() -> bool {
+    var("ADAPTIVE_POLLING").unwrap_or_default() == "true"
+}
+
+fn fast_interval() -> Duration {
+    let minutes = var("ADAPTIVE_INTERVAL")
+        .ok()
+        .and_then(|minutes| minutes.parse().ok())
+        .unwrap_or(15);
+    Duration::from_secs(minutes * 60)
+}
+
+// If one of the stored shifts is on the day after today
+pub fn shift_tomorrow(today: NaiveDate) -> bool {
+    let shifts: Vec<Shift> = load_stored_shifts().unwrap_or_default();
+    today
+        .succ_opt()
+        .and_then(|tomorrow| to_date(tomorrow).ok())
+        .is_some_and(|tomorrow| shifts.iter().any(|shift| shift.date == tomorrow))
+}
+
+fn to_date(date: NaiveDate) -> GenResult<Date> {
+    Ok(Date::from_calendar_date(
+        date.year(),
+        Month::try_from(date.month() as u8)?,
+        date.day() as u8,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(month: u32, day: u32, hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2025, month, day)
+            .unwrap()
+            .and_hms_opt(hour, 15, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn busy_times_are_learned_from_history() {
+        let mut history = ChangeHistory::default();
+        history.record(at(4, 20, 14)).unwrap();
+        assert!(!history.is_busy_time(at(5, 20, 14)));
+        history.record(at(5, 21, 14)).unwrap();
+        assert!(history.is_busy_time(at(6, 20, 14)));
+        assert!(history.is_busy_time(at(6, 21, 14)));
+        assert!(!history.is_busy_time(at(6, 23, 14)));
+        assert!(!history.is_busy_time(at(6, 20, 9)));
+        assert!(!history.is_busy_time(at(6, 25, 14)));
+    }
+
+    #[test]
+    fn busy_times_cross_month_boundaries() {
+        let mut history = ChangeHistory::default();
+        history.record(at(4, 30, 14)).unwrap();
+        history.record(at(5, 31, 14)).unwrap();
+        assert!(history.is_busy_time(at(6, 1, 14)));
+        assert!(history.is_busy_time(at(6, 30, 14)));
+        assert!(!history.is_busy_time(at(6, 2, 14)));
+        assert!(!history.is_busy_time(at(6, 28, 14)));
+    }
+
+    #[test]
+    fn polling_speeds_up_and_backs_off() {
+        let history = ChangeHistory::default();
+        let hour = Duration::from_secs(3600);
+        let mut logbook = ApplicationLogbook::default();
+        let mode = |logbook: &ApplicationLogbook, shift_tomorrow| {
+            PollingMode::decide(&history, at(6, 1, 12), shift_tomorrow, logbook)
+        };
+        assert_eq!(mode(&logbook, false), PollingMode::Normal);
+        assert_eq!(mode(&logbook, true), PollingMode::Fast);
+        assert_eq!(PollingMode::Fast.wait(hour, MAX_BACKOFF), fast_interval());
+
+        logbook.state = FailureType::SignInFailed(SignInFailure::WebcomDown);
+        // The first failure has a repeat count of 0
+        logbook.repeat_count = 1;
+        assert_eq!(mode(&logbook, true), PollingMode::Backoff(2));
+        assert_eq!(PollingMode::Backoff(2).wait(hour, MAX_BACKOFF), hour * 4);
+        assert_eq!(
+            PollingMode::Backoff(20).wait(hour, MAX_BACKOFF),
+            MAX_BACKOFF
+        );
+        // Kuma would report the user as down if the wait was longer than its heartbeat interval
+        assert_eq!(PollingMode::Backoff(20).wait(hour, hour * 3), hour * 3);
+    }
+}
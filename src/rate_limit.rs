@@ -0,0 +1,185 @@
+use std::{
+    fs::{File, OpenOptions, create_dir_all, read_to_string, write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use dotenvy::var;
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+use crate::GenResult;
+
+const STATE_FILENAME: &str = "logins.json";
+const LOCK_FILENAME: &str = "logins.lock";
+// A waiting instance which has not checked the queue for this long has stopped, and loses its place
+const STALE_TICKET: Duration = Duration::from_secs(60);
+// How often a waiting instance checks the queue
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+// After this long the login is done anyway, so a broken rate limiter never stops webcom ical
+const MAX_WAIT: Duration = Duration::from_secs(15 * 60);
+
+// A waiting instance
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Ticket {
+    id: String,
+    // Seconds since the unix epoch
+    last_seen: f64,
+}
+
+/*
+The token bucket shared by every webcom ical instance on this host, with a queue so instances sign in in the order they asked
+The bucket holds at most one login, which is refilled at MAX_LOGINS_PER_MINUTE, so logins are spread evenly
+*/
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct RateLimitState {
+    tokens: f64,
+    // Seconds since the unix epoch
+    updated: f64,
+    queue: Vec<Ticket>,
+}
+
+impl RateLimitState {
+    /*
+    Takes a login for the ticket if it is first in the queue and a login is available
+    Otherwise the ticket is put in the queue and the time until the next login is returned
+    */
+    fn try_take(&mut self, id: &str, now: f64, logins_per_minute: f64) -> Result<(), Duration> {
+        let seconds_per_login = 60.0 / logins_per_minute;
+        self.tokens = (self.tokens + (now - self.updated).max(0.0) / seconds_per_login).min(1.0);
+        self.updated = now;
+        self.queue
+            .retain(|ticket| now - ticket.last_seen < STALE_TICKET.as_secs_f64());
+        match self.queue.iter_mut().find(|ticket| ticket.id == id) {
+            Some(ticket) => ticket.last_seen = now,
+            None => self.queue.push(Ticket {
+                id: id.to_owned(),
+                last_seen: now,
+            }),
+        }
+        if self.queue[0].id == id && self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            self.queue.remove(0);
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64(
+            (1.0 - self.tokens) * seconds_per_login,
+        ))
+    }
+}
+
+// The directory shared by all instances, set with RATE_LIMIT_DIRECTORY. None means there is no rate limit
+fn rate_limit_directory() -> Option<PathBuf> {
+    var("RATE_LIMIT_DIRECTORY")
+        .ok()
+        .filter(|directory| !directory.trim().is_empty())
+        .map(PathBuf::from)
+}
+
+fn logins_per_minute() -> f64 {
+    var("MAX_LOGINS_PER_MINUTE")
+        .ok()
+        .and_then(|logins| logins.parse::<f64>().ok())
+        .filter(|logins| *logins > 0.0)
+        .unwrap_or(6.0)
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+// Takes a login while holding the lock file, so only one instance changes the bucket at a time
+fn try_take_login(directory: &Path, id: &str) -> GenResult<Result<(), Duration>> {
+    create_dir_all(directory)?;
+    let lock_file: File = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(directory.join(LOCK_FILENAME))?;
+    lock_file.lock()?;
+    let state_path = directory.join(STATE_FILENAME);
+    let mut state: RateLimitState = read_to_string(&state_path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+    let result = state.try_take(id, unix_now(), logins_per_minute());
+    write(&state_path, serde_json::to_string_pretty(&state)?)?;
+    Ok(result)
+}
+
+/*
+Waits until this instance may sign in to webcom
+A random wait is added to every check, so instances which started at the same moment do not check at the same moment
+*/
+pub async fn wait_for_login(username: &str) {
+    let Some(directory) = rate_limit_directory() else {
+        return;
+    };
+    let id = format!("{username}-{}", std::process::id());
+    let started = SystemTime::now();
+    loop {
+        // Another instance can hold the lock file, so it is waited for outside of the async runtime
+        let (lock_directory, lock_id) = (directory.clone(), id.clone());
+        let result = tokio::task::spawn_blocking(move || try_take_login(&lock_directory, &lock_id))
+            .await
+            .unwrap_or_else(|error| Err(std::io::Error::from(error).into()));
+        let wait = match result {
+            Ok(Ok(())) => return,
+            Ok(Err(wait)) => wait,
+            Err(error) => {
+                warn!("Rate limiter could not be used, signing in anyway. Error: {error}");
+                return;
+            }
+        };
+        if started.elapsed().unwrap_or_default() > MAX_WAIT {
+            warn!("Waited more than {MAX_WAIT:?} for the rate limiter, signing in anyway");
+            return;
+        }
+        debug!("Waiting for the rate limiter, next login in {wait:?}");
+        let jitter = Duration::from_millis(rand::random_range(0..1000));
+        sleep(wait.clamp(Duration::from_millis(100), POLL_INTERVAL) + jitter).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logins_are_spread_and_taken_in_order() {
+        let mut state = RateLimitState::default();
+        // 6 logins per minute is one every 10 seconds
+        assert_eq!(state.try_take("a", 1000.0, 6.0), Ok(()));
+        assert!(state.try_take("b", 1001.0, 6.0).is_err());
+        assert!(state.try_take("c", 1002.0, 6.0).is_err());
+        // c asked after b, so it has to wait for b
+        assert!(state.try_take("c", 1011.0, 6.0).is_err());
+        assert_eq!(state.try_take("b", 1011.0, 6.0), Ok(()));
+        assert_eq!(
+            state.try_take("c", 1016.0, 6.0),
+            Err(Duration::from_secs(5))
+        );
+        assert_eq!(state.try_take("c", 1021.0, 6.0), Ok(()));
+        assert!(state.queue.is_empty());
+    }
+
+    #[test]
+    fn stopped_instances_lose_their_place() {
+        let mut state = RateLimitState::default();
+        assert!(state.try_take("a", 0.0, 6.0).is_err());
+        assert!(state.try_take("b", 1.0, 6.0).is_err());
+        assert_eq!(state.try_take("b", 100.0, 6.0), Ok(()));
+    }
+
+    #[test]
+    fn lock_file_is_shared_between_instances() {
+        let directory =
+            std::env::temp_dir().join(format!("webcom_rate_limit_{}", std::process::id()));
+        assert_eq!(try_take_login(&directory, "a").unwrap(), Ok(()));
+        assert!(try_take_login(&directory, "b").unwrap().is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
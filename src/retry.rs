@@ -0,0 +1,274 @@
+use std::{
+    fs::{read_to_string, write},
+    time::Duration,
+};
+
+use chrono::{DateTime, Local};
+use dotenvy::var;
+use serde::{Deserialize, Serialize};
+
+use crate::{FailureType, GenResult, SignInFailure, create_path, errors::WebcomError};
+
+const CIRCUIT_BREAKER_FILENAME: &str = "circuit_breaker.json";
+
+// What kind of failure a try ended in, every class has its own number of tries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureClass {
+    // A page which did not load or timed out, trying again usually works
+    Transient,
+    // Webcom showed text which could not be read
+    Parse,
+    WebcomDown,
+    Connect,
+    // Incorrect credentials or too many tries, trying again only makes it worse
+    SignIn,
+    // Fixing the configuration is needed, trying again does not help
+    Config,
+}
+
+impl FailureClass {
+    pub fn of(error: &WebcomError) -> Self {
+        match error {
+            WebcomError::Failure(FailureType::SignInFailed(SignInFailure::WebcomDown)) => {
+                Self::WebcomDown
+            }
+            WebcomError::Failure(FailureType::SignInFailed(_)) => Self::SignIn,
+            WebcomError::Failure(FailureType::ConnectError) => Self::Connect,
+            WebcomError::Parsing { .. } | WebcomError::Missing(_) => Self::Parse,
+            WebcomError::Config { .. } => Self::Config,
+            _ => Self::Transient,
+        }
+    }
+}
+
+// A failed try and what was decided after it, saved in the run history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetryStep {
+    pub attempt: usize,
+    pub class: FailureClass,
+    pub error: String,
+    // The wait before the next try, None if the run gave up
+    pub delay_seconds: Option<f64>,
+}
+
+/*
+How often and how long to wait before trying again, per failure class
+The wait doubles every try of the same class, with a random part so instances do not retry at the same moment
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub transient_tries: usize,
+    pub parse_tries: usize,
+    pub webcom_down_tries: usize,
+    pub connect_tries: usize,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    // Sign in and connection failures are not retried, like before there was a retry policy
+    fn default() -> Self {
+        Self {
+            transient_tries: 3,
+            parse_tries: 2,
+            webcom_down_tries: 1,
+            connect_tries: 1,
+            base_delay: Duration::from_secs(5),
+            max_delay: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let number = |name: &str, default: u64| {
+            var(name)
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            transient_tries: number("RETRY_COUNT", default.transient_tries as u64) as usize,
+            parse_tries: number("RETRY_COUNT_PARSE", default.parse_tries as u64) as usize,
+            webcom_down_tries: number("RETRY_COUNT_WEBCOM_DOWN", default.webcom_down_tries as u64)
+                as usize,
+            connect_tries: number("RETRY_COUNT_CONNECT", default.connect_tries as u64) as usize,
+            base_delay: Duration::from_secs(number(
+                "RETRY_BASE_DELAY",
+                default.base_delay.as_secs(),
+            )),
+            max_delay: Duration::from_secs(number("RETRY_MAX_DELAY", default.max_delay.as_secs())),
+        }
+    }
+
+    fn tries(&self, class: FailureClass) -> usize {
+        match class {
+            FailureClass::Transient => self.transient_tries,
+            FailureClass::Parse => self.parse_tries,
+            FailureClass::WebcomDown => self.webcom_down_tries,
+            FailureClass::Connect => self.connect_tries,
+            FailureClass::SignIn | FailureClass::Config => 1,
+        }
+    }
+
+    /*
+    Decides what to do after the given number of failures of a class, None means giving up
+    Half of the wait is fixed and half is random
+    */
+    pub fn decide(&self, class: FailureClass, failures: usize) -> Option<Duration> {
+        if failures >= self.tries(class) {
+            return None;
+        }
+        let exponent = failures.saturating_sub(1).min(16) as u32;
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.pow(exponent))
+            .min(self.max_delay);
+        Some(delay / 2 + delay.mul_f64(rand::random_range(0.0..0.5)))
+    }
+}
+
+// What the circuit breaker did for a run, saved in the run history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "decision", rename_all = "snake_case")]
+pub enum CircuitDecision {
+    // The run was skipped, as webcom was down the last runs
+    Paused { until: String },
+    // Webcom was down too many runs in a row, the next runs are skipped
+    Opened { until: String },
+}
+
+/*
+Pauses this user after webcom was down CIRCUIT_BREAKER_THRESHOLD runs in a row, for CIRCUIT_BREAKER_PAUSE minutes
+After the pause a single run is tried, if webcom is still down the pause starts again
+*/
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CircuitBreaker {
+    pub webcom_down_runs: u32,
+    // RFC 3339
+    pub open_until: Option<String>,
+}
+
+impl CircuitBreaker {
+    pub fn load() -> Self {
+        read_to_string(create_path(CIRCUIT_BREAKER_FILENAME))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> GenResult<()> {
+        write(
+            create_path(CIRCUIT_BREAKER_FILENAME),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+
+    // Until when runs are skipped, None if runs are allowed
+    pub fn paused_until(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        self.open_until
+            .as_deref()
+            .and_then(|until| DateTime::parse_from_rfc3339(until).ok())
+            .map(|until| until.with_timezone(&Local))
+            .filter(|until| *until > now)
+    }
+
+    // Called after every run which was not skipped
+    pub fn record(
+        &mut self,
+        result: &FailureType,
+        now: DateTime<Local>,
+        threshold: u32,
+        pause: chrono::Duration,
+    ) -> Option<CircuitDecision> {
+        if *result != FailureType::SignInFailed(SignInFailure::WebcomDown) {
+            *self = Self::default();
+            return None;
+        }
+        self.webcom_down_runs += 1;
+        if threshold == 0 || self.webcom_down_runs < threshold {
+            return None;
+        }
+        let until = (now + pause).to_rfc3339();
+        self.open_until = Some(until.clone());
+        Some(CircuitDecision::Opened { until })
+    }
+}
+
+// 0 turns the circuit breaker off
+pub fn circuit_breaker_threshold() -> u32 {
+    var("CIRCUIT_BREAKER_THRESHOLD")
+        .ok()
+        .and_then(|threshold| threshold.parse().ok())
+        .unwrap_or(3)
+}
+
+pub fn circuit_breaker_pause() -> chrono::Duration {
+    chrono::Duration::minutes(
+        var("CIRCUIT_BREAKER_PAUSE")
+            .ok()
+            .and_then(|minutes| minutes.parse().ok())
+            .unwrap_or(60),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_class_has_its_own_tries_and_backoff() {
+        let policy = RetryPolicy::default();
+        for failures in 1..3 {
+            let delay = policy.decide(FailureClass::Transient, failures).unwrap();
+            let full_delay = Duration::from_secs(5 * 2u64.pow(failures as u32 - 1));
+            assert!(delay >= full_delay / 2 && delay < full_delay);
+        }
+        assert_eq!(policy.decide(FailureClass::Transient, 3), None);
+        assert!(policy.decide(FailureClass::Parse, 1).is_some());
+        assert_eq!(policy.decide(FailureClass::Parse, 2), None);
+        assert_eq!(policy.decide(FailureClass::WebcomDown, 1), None);
+        assert_eq!(policy.decide(FailureClass::SignIn, 1), None);
+
+        let capped = RetryPolicy {
+            transient_tries: 20,
+            ..RetryPolicy::default()
+        };
+        assert!(capped.decide(FailureClass::Transient, 19).unwrap() < capped.max_delay);
+    }
+
+    #[test]
+    fn errors_are_classified_by_variant() {
+        let down = WebcomError::from(FailureType::SignInFailed(SignInFailure::WebcomDown));
+        assert_eq!(FailureClass::of(&down), FailureClass::WebcomDown);
+        let parse = WebcomError::from("x".parse::<u8>().unwrap_err());
+        assert_eq!(FailureClass::of(&parse), FailureClass::Parse);
+        let page = WebcomError::scraping("Geen ritten tabel gevonden");
+        assert_eq!(FailureClass::of(&page), FailureClass::Transient);
+    }
+
+    #[test]
+    fn circuit_opens_after_webcom_is_down_repeatedly() {
+        let now = Local::now();
+        let pause = chrono::Duration::minutes(60);
+        let down = FailureType::SignInFailed(SignInFailure::WebcomDown);
+        let mut breaker = CircuitBreaker::default();
+        assert_eq!(breaker.record(&down, now, 3, pause), None);
+        assert_eq!(breaker.record(&down, now, 3, pause), None);
+        assert!(matches!(
+            breaker.record(&down, now, 3, pause),
+            Some(CircuitDecision::Opened { .. })
+        ));
+        assert!(breaker.paused_until(now).is_some());
+        assert_eq!(breaker.paused_until(now + pause), None);
+
+        // Still down after the pause, so the pause starts again right away
+        let later = now + pause;
+        assert!(breaker.record(&down, later, 3, pause).is_some());
+        breaker.record(&FailureType::OK, later, 3, pause);
+        assert_eq!(breaker, CircuitBreaker::default());
+    }
+}
@@ -0,0 +1,194 @@
+use chrono::{Duration, NaiveDateTime, NaiveTime};
+use dotenvy::var;
+
+use crate::{
+    GenResult,
+    errors::{OptionResult, WebcomError},
+    execution::get_starting_minute,
+};
+
+// The margin kuma gets on top of the longest time between two runs, same as the ansible playbook uses
+pub const HEARTBEAT_MARGIN_SECONDS: i64 = 400;
+
+// A part of the day in which webcom is checked every interval_minutes
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduleWindow {
+    pub start: NaiveTime,
+    // If the end is before the start, the window continues until the end on the next day
+    pub end: NaiveTime,
+    pub interval_minutes: u32,
+}
+
+/*
+When webcom should be checked, set with SCHEDULE in local time
+e.g. SCHEDULE=07:00-18:00/30,18:00-23:00/60 checks every 30 minutes during the day, every hour in the evening and not at night
+Runs are shifted by the starting minute, so users with the same schedule do not all check webcom at the same moment
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    pub windows: Vec<ScheduleWindow>,
+    pub offset_minutes: u32,
+}
+
+impl ScheduleWindow {
+    // Parses a single window, e.g. 07:00-18:00/30
+    fn parse(text: &str) -> GenResult<Self> {
+        let (times, interval) = text.split_once('/').result("schedule interval")?;
+        let (start, end) = times.split_once('-').result("schedule window end")?;
+        let interval_minutes: u32 = interval.trim().parse()?;
+        if interval_minutes == 0 {
+            return Err(WebcomError::config(format!(
+                "Interval of schedule window {text} is 0"
+            )));
+        }
+        Ok(Self {
+            start: NaiveTime::parse_from_str(start.trim(), "%H:%M")?,
+            end: NaiveTime::parse_from_str(end.trim(), "%H:%M")?,
+            interval_minutes,
+        })
+    }
+}
+
+impl Schedule {
+    pub fn parse(text: &str, offset_minutes: u32) -> GenResult<Self> {
+        let windows = text
+            .split(',')
+            .filter(|window| !window.trim().is_empty())
+            .map(ScheduleWindow::parse)
+            .collect::<GenResult<Vec<ScheduleWindow>>>()?;
+        if windows.is_empty() {
+            return Err(WebcomError::config("Schedule has no windows"));
+        }
+        Ok(Self {
+            windows,
+            offset_minutes,
+        })
+    }
+
+    // None if SCHEDULE is not set or is wrong, then CYCLE_TIME is used instead
+    pub fn from_env() -> Option<Self> {
+        let text = var("SCHEDULE")
+            .ok()
+            .filter(|text| !text.trim().is_empty())?;
+        Self::parse(&text, get_starting_minute() as u32)
+            .inspect_err(|error| {
+                warn!("SCHEDULE \"{text}\" is not valid, using CYCLE_TIME. Error: {error}")
+            })
+            .ok()
+    }
+
+    // The first run after the given moment
+    pub fn next_run(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut next_run: Option<NaiveDateTime> = None;
+        // A window of yesterday can continue until today
+        for day_offset in -1..=1 {
+            let day = after.date() + Duration::days(day_offset);
+            for window in &self.windows {
+                let start = day.and_time(window.start);
+                let mut end = day.and_time(window.end);
+                if end <= start {
+                    end += Duration::days(1);
+                }
+                let interval = Duration::minutes(window.interval_minutes as i64);
+                let mut run = start
+                    + Duration::minutes((self.offset_minutes % window.interval_minutes) as i64);
+                if run <= after {
+                    let passed_intervals = (after - run).num_minutes() / interval.num_minutes();
+                    run += interval * (passed_intervals as i32 + 1);
+                }
+                if run < end && next_run.is_none_or(|next_run| run < next_run) {
+                    next_run = Some(run);
+                }
+            }
+        }
+        next_run
+    }
+
+    // If the moment is inside one of the windows
+    pub fn is_active(&self, moment: NaiveDateTime) -> bool {
+        (-1..=0).any(|day_offset| {
+            let day = moment.date() + Duration::days(day_offset);
+            self.windows.iter().any(|window| {
+                let start = day.and_time(window.start);
+                let mut end = day.and_time(window.end);
+                if end <= start {
+                    end += Duration::days(1);
+                }
+                start <= moment && moment < end
+            })
+        })
+    }
+
+    // The longest time between two runs, which is often the night
+    pub fn longest_gap(&self) -> Duration {
+        let midnight = NaiveDateTime::default();
+        let mut longest_gap = Duration::zero();
+        let Some(mut previous_run) = self.next_run(midnight) else {
+            return longest_gap;
+        };
+        while let Some(run) = self.next_run(previous_run)
+            && run <= midnight + Duration::days(2)
+        {
+            longest_gap = longest_gap.max(run - previous_run);
+            previous_run = run;
+        }
+        longest_gap
+    }
+}
+
+/*
+How many seconds kuma waits for a heartbeat before the user is down
+With a schedule this is the longest time between two runs, so kuma does not go down every night
+*/
+pub fn kuma_heartbeat_interval() -> GenResult<i32> {
+    match Schedule::from_env() {
+        Some(schedule) => {
+            Ok((schedule.longest_gap().num_seconds() + HEARTBEAT_MARGIN_SECONDS) as i32)
+        }
+        None => Ok(var("KUMA_HEARTBEAT_INTERVAL")?.parse()?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2025, 6, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn runs_follow_the_windows() {
+        let schedule = Schedule::parse("07:00-18:00/30, 18:00-23:00/60", 10).unwrap();
+        assert_eq!(schedule.next_run(at(2, 3, 0)), Some(at(2, 7, 10)));
+        assert_eq!(schedule.next_run(at(2, 7, 10)), Some(at(2, 7, 40)));
+        assert_eq!(schedule.next_run(at(2, 17, 45)), Some(at(2, 18, 10)));
+        assert_eq!(schedule.next_run(at(2, 22, 10)), Some(at(3, 7, 10)));
+        assert_eq!(
+            schedule.longest_gap(),
+            Duration::hours(9),
+            "From 22:10 until 7:10"
+        );
+    }
+
+    #[test]
+    fn windows_can_continue_after_midnight() {
+        let schedule = Schedule::parse("22:00-02:00/120", 0).unwrap();
+        assert_eq!(schedule.next_run(at(2, 23, 0)), Some(at(3, 0, 0)));
+        assert_eq!(schedule.next_run(at(3, 0, 0)), Some(at(3, 22, 0)));
+        assert!(schedule.is_active(at(3, 1, 0)));
+        assert!(!schedule.is_active(at(3, 2, 0)));
+    }
+
+    #[test]
+    fn wrong_schedules_are_refused() {
+        assert!(Schedule::parse("", 0).is_err());
+        assert!(Schedule::parse("07:00-18:00", 0).is_err());
+        assert!(Schedule::parse("07:00-18:00/0", 0).is_err());
+        assert!(Schedule::parse("7-18/30", 0).is_err());
+    }
+}
@@ -1,13 +1,14 @@
-use std::{
-    hash::{DefaultHasher, Hash, Hasher},
-    str::Split,
-};
+use std::str::Split;
 
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DefaultOnError};
 use time::{Date, Duration, Time};
 
-use crate::{GenResult, errors::OptionResult};
+use crate::{
+    GenResult,
+    errors::OptionResult,
+    holidays::{Holiday, holiday_on},
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
 pub enum ShiftState {
@@ -62,8 +63,8 @@ impl Shift {
             .collect();
         let mut parts_list: Vec<Split<'_, &str>> =
             parts_clean.iter().map(|x| x.split(": ")).collect();
-        let number: String = parts_list[0].nth(1).result()?.to_string();
-        let _date: String = parts_list[1].nth(1).result()?.to_string();
+        let number: String = parts_list[0].nth(1).result("shift number")?.to_string();
+        let _date: String = parts_list[1].nth(1).result("shift date")?.to_string();
         let time: String = parts_list[2].nth(1).unwrap_or("").to_string();
         let shift_duration: String = parts_list[3].nth(1).unwrap_or("").to_string();
         let _working_hours: String = parts_list[4].nth(1).unwrap_or("").to_string();
@@ -78,16 +79,12 @@ impl Shift {
             .nth(1)
             .unwrap_or("")
             .to_string();
-        let start_time_str = time.split_whitespace().nth(0).result()?;
-        let end_time_str = time.split_whitespace().nth(2).result()?;
+        let start_time_str = time.split_whitespace().nth(0).result("shift start time")?;
+        let end_time_str = time.split_whitespace().nth(2).result("shift end time")?;
         let start = Shift::get_time(start_time_str)?;
         let end = Shift::get_time(end_time_str)?;
         let mut is_broken = false;
-        let shift_type = number.chars().nth(0).result()?;
-        let mut hasher = DefaultHasher::new();
-        let hash_list = (date, &number, &start, &end, &shift_duration);
-        hash_list.hash(&mut hasher);
-        let magic_number = (hasher.finish() as i128 - i64::MAX as i128) as i64;
+        let shift_type = number.chars().nth(0).result("shift type")?;
         if shift_type == 'g' || shift_type == 'G' {
             is_broken = true;
         }
@@ -95,18 +92,28 @@ impl Shift {
         let duration_split = shift_duration
             .split_whitespace()
             .nth(0)
-            .result()?
+            .result("shift duration")?
             .split(":");
-        let duration_minutes =
-            Duration::minutes(duration_split.clone().nth(1).result()?.parse::<i64>()?);
-        let duration_hours =
-            Duration::hours(duration_split.clone().nth(0).result()?.parse::<i64>()?);
+        let duration_minutes = Duration::minutes(
+            duration_split
+                .clone()
+                .nth(1)
+                .result("shift duration minutes")?
+                .parse::<i64>()?,
+        );
+        let duration_hours = Duration::hours(
+            duration_split
+                .clone()
+                .nth(0)
+                .result("shift duration hours")?
+                .parse::<i64>()?,
+        );
         let duration = duration_hours + duration_minutes;
         let mut end_date = date;
         if end < start {
             end_date = date + Duration::days(1);
         }
-        Ok(Self {
+        let mut shift = Self {
             date,
             number,
             start,
@@ -119,9 +126,28 @@ impl Shift {
             is_broken,
             broken_period: None,
             original_end_time: None,
-            magic_number,
+            magic_number: 0,
             state: ShiftState::Unknown,
-        })
+        };
+        shift.magic_number = shift.calculate_magic_number();
+        Ok(shift)
+    }
+
+    /*
+    The id of the shift, which changes when the date, number, times or duration of the shift change
+    Uses FNV-1a instead of the hashers of std, so it is the same on every platform and Rust version
+    Must be called on the shift as it was loaded from webcom, not on the parts of a split shift
+    */
+    pub fn calculate_magic_number(&self) -> i64 {
+        let text = format!(
+            "{}|{}|{}|{}|{}",
+            self.date,
+            self.number,
+            self.start,
+            self.end,
+            self.duration.whole_minutes()
+        );
+        fnv1a(text.as_bytes()) as i64
     }
 
     // Create new shifts from one broken shift.
@@ -167,14 +193,97 @@ impl Shift {
         shifts
     }
 
+    // Returns the public holiday the shift starts on, if any
+    pub fn holiday(&self) -> Option<Holiday> {
+        holiday_on(self.date)
+    }
+
     // Creates and returns a Time::time from a given string of time eg: 12:34
     fn get_time(str_time: &str) -> GenResult<Time> {
         let mut time_split = str_time.split(":");
-        let mut hour: u8 = time_split.clone().next().result()?.parse()?;
-        let min: u8 = time_split.nth(1).result()?.parse()?;
+        let mut hour: u8 = time_split.clone().next().result("hour")?.parse()?;
+        let min: u8 = time_split.nth(1).result("minute")?.parse()?;
         if hour >= 24 {
             hour = hour - 24;
         }
         Ok(Time::from_hms(hour, min, 0)?)
     }
 }
+
+// The 64 bit FNV-1a hash, used for everything which is stored, as it gives the same hash on every platform and Rust version
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// The parts of a shift which webcom shows in its tooltip, used by the tests and the mock server
+#[cfg(any(test, feature = "mock-server"))]
+pub struct TooltipShift<'a> {
+    pub number: &'a str,
+    pub start: &'a str,
+    pub end: &'a str,
+    pub duration: &'a str,
+    pub kind: &'a str,
+    pub location: &'a str,
+    pub description: &'a str,
+}
+
+#[cfg(any(test, feature = "mock-server"))]
+impl TooltipShift<'_> {
+    // The tooltip webcom shows when hovering over the day of the shift in the roster
+    pub fn tooltip(&self, date: Date) -> String {
+        format!(
+            "Dienst: {}\u{a0}• \u{a0}• Geldig vanaf: {}\u{a0}• \u{a0}• Tijd: {} - {}\u{a0}• \u{a0}• Dienstduur: {} Uren\u{a0}• \u{a0}• Loonuren: {} Uren\u{a0}• \u{a0}• Dagsoort: \u{a0}• Maandag\u{a0}• \u{a0}• Dienstsoort: \u{a0}• {}\u{a0}• \u{a0}• Startplaats: \u{a0}• {}\u{a0}• \u{a0}• Omschrijving: \u{a0}• {}",
+            self.number,
+            date.format(crate::email::DATE_DESCRIPTION)
+                .unwrap_or_default(),
+            self.start,
+            self.end,
+            self.duration,
+            self.duration,
+            self.kind,
+            self.location,
+            self.description
+        )
+    }
+}
+
+// The tooltip webcom shows for a shift, to create shifts in tests without a webcom page
+#[cfg(test)]
+pub fn test_tooltip(date: Date, number: &str, start: &str, end: &str) -> String {
+    TooltipShift {
+        number,
+        start,
+        end,
+        duration: "08:00",
+        kind: "Rijdienst",
+        location: "Eindhoven",
+        description: "Lijn 401",
+    }
+    .tooltip(date)
+}
+
+#[cfg(test)]
+impl Shift {
+    // A shift from 06:00 to 14:00, as if it was read from webcom
+    pub fn test_shift(date: Date, number: &str) -> Self {
+        Self::new(test_tooltip(date, number, "06:00", "14:00"), date).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Month;
+
+    #[test]
+    fn magic_number_is_stable() {
+        let date = Date::from_calendar_date(2025, Month::June, 2).unwrap();
+        let mut shift = Shift::test_shift(date, "V1");
+        // The id is stored and compared with the ids of earlier runs, so it may never change by itself
+        assert_eq!(shift.magic_number, -3633341779629287408);
+        shift.end = Shift::get_time("14:30").unwrap();
+        assert_ne!(shift.calculate_magic_number(), shift.magic_number);
+    }
+}
@@ -0,0 +1,219 @@
+use std::{
+    fs::{self, File, OpenOptions, TryLockError, read_to_string, write},
+    path::Path,
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    FailureType, GenResult, create_path,
+    errors::{IncorrectCredentialsCount, SignInFailure},
+    health::ApplicationLogbook,
+};
+
+const NEXT_RUN_FILENAME: &str = "next_run";
+// Written by main_loop while a run is in progress, contains the start reason
+const ACTIVE_FILENAME: &str = "active";
+// Locked while a run or a backfill changes the stored shifts and the calendar
+const RUN_LOCK_FILENAME: &str = "run.lock";
+
+// A run which is in progress
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActiveRun {
+    pub start_reason: String,
+    pub elapsed_seconds: u64,
+}
+
+/*
+Everything there is to know about the runs of this instance, read from the files in the kuma directory
+So it also works when webcom ical is not running
+*/
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunStatus {
+    pub running: Option<ActiveRun>,
+    pub last_state: FailureType,
+    // How many runs in a row ended in last_state
+    pub repeat_count: u64,
+    pub shifts: u64,
+    pub broken_shifts: u64,
+    pub non_relevant_shifts: u64,
+    pub failed_shifts: u64,
+    // RFC 3339, None if there is no timer, like with a single run
+    pub next_run: Option<String>,
+    pub sign_in_failures: usize,
+    pub sign_in_error: Option<SignInFailure>,
+}
+
+impl RunStatus {
+    pub fn load() -> Self {
+        let logbook = ApplicationLogbook::load();
+        let sign_in_failures = IncorrectCredentialsCount::load();
+        Self {
+            running: active_run(),
+            last_state: logbook.state,
+            repeat_count: logbook.repeat_count,
+            shifts: logbook.application_state.shifts,
+            broken_shifts: logbook.application_state.broken_shifts,
+            non_relevant_shifts: logbook.application_state.non_relevant_shifts,
+            failed_shifts: logbook.application_state.failed_shifts,
+            next_run: read_to_string(create_path(NEXT_RUN_FILENAME))
+                .ok()
+                .map(|next_run| next_run.trim().to_owned()),
+            sign_in_failures: sign_in_failures.retry_count,
+            sign_in_error: sign_in_failures.error,
+        }
+    }
+
+    pub fn human(&self) -> String {
+        let running = match &self.running {
+            Some(run) => format!(
+                "yes, started by {} {} seconds ago",
+                run.start_reason, run.elapsed_seconds
+            ),
+            None => "no".to_owned(),
+        };
+        let next_run = self
+            .next_run
+            .as_deref()
+            .and_then(|next_run| DateTime::parse_from_rfc3339(next_run).ok())
+            .map(|next_run| {
+                next_run
+                    .with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or("not planned".to_owned());
+        let sign_in_error = self
+            .sign_in_error
+            .as_ref()
+            .map(|error| format!(" ({error})"))
+            .unwrap_or_default();
+        format!(
+            "Running: {running}
+Last result: {} (repeated {} times)
+Shifts: {} ({} broken, {} non-relevant, {} failed)
+Next run: {next_run}
+Sign in failures: {}{sign_in_error}",
+            self.last_state,
+            self.repeat_count,
+            self.shifts,
+            self.broken_shifts,
+            self.non_relevant_shifts,
+            self.failed_shifts,
+            self.sign_in_failures,
+        )
+    }
+}
+
+// The run in progress, None if webcom ical is not running or waiting for the next run
+pub fn active_run() -> Option<ActiveRun> {
+    let path = create_path(ACTIVE_FILENAME);
+    let content = read_to_string(&path).ok()?;
+    let elapsed_seconds = fs::metadata(&path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|started| SystemTime::now().duration_since(started).ok())
+        .unwrap_or_default()
+        .as_secs();
+    Some(ActiveRun {
+        start_reason: serde_json::from_str(&content).unwrap_or(content),
+        elapsed_seconds,
+    })
+}
+
+/*
+Takes the run lock, None if a run or a backfill of this instance already holds it
+The lock is released when the returned file is dropped, or when the process stops
+*/
+pub fn try_lock_run() -> GenResult<Option<File>> {
+    try_lock_run_at(&create_path(RUN_LOCK_FILENAME))
+}
+
+fn try_lock_run_at(path: &Path) -> GenResult<Option<File>> {
+    let lock_file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    match lock_file.try_lock() {
+        Ok(()) => Ok(Some(lock_file)),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(error)) => Err(error.into()),
+    }
+}
+
+// Waits until the run lock is free, so a run never starts while a backfill is changing the stored shifts
+pub async fn lock_run() -> GenResult<File> {
+    if let Some(lock_file) = try_lock_run()? {
+        return Ok(lock_file);
+    }
+    info!("A backfill is in progress, waiting for it to finish before running");
+    let lock_file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(create_path(RUN_LOCK_FILENAME))?;
+    let lock_file = tokio::task::spawn_blocking(move || lock_file.lock().map(|()| lock_file))
+        .await
+        .map_err(std::io::Error::from)??;
+    Ok(lock_file)
+}
+
+// Remembers when the timer starts the next run, for the status command
+pub fn save_next_run(next_run: DateTime<Local>) -> GenResult<()> {
+    write(create_path(NEXT_RUN_FILENAME), next_run.to_rfc3339())?;
+    Ok(())
+}
+
+// The status command, in a human readable form or as JSON
+pub fn print_status(json: bool) -> GenResult<()> {
+    let status = RunStatus::load();
+    match json {
+        true => println!("{}", serde_json::to_string_pretty(&status)?),
+        false => println!("{}", status.human()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn human_status_shows_every_part() {
+        let status = RunStatus {
+            running: Some(ActiveRun {
+                start_reason: "Timer".to_owned(),
+                elapsed_seconds: 42,
+            }),
+            last_state: FailureType::OK,
+            repeat_count: 3,
+            shifts: 40,
+            broken_shifts: 2,
+            non_relevant_shifts: 100,
+            failed_shifts: 1,
+            next_run: Some("2025-06-02T14:10:00+02:00".to_owned()),
+            sign_in_failures: 1,
+            sign_in_error: Some(SignInFailure::TooManyTries),
+        };
+        let human = status.human();
+        assert!(human.contains("started by Timer 42 seconds ago"));
+        assert!(human.contains("Ok (repeated 3 times)"));
+        assert!(human.contains("Shifts: 40 (2 broken, 100 non-relevant, 1 failed)"));
+        assert!(human.contains("Sign in failures: 1 (Er zijn te veel"));
+        assert!(!human.contains("not planned"));
+    }
+
+    #[test]
+    fn run_lock_is_held_until_dropped() {
+        let path = std::env::temp_dir().join(format!("webcom_run_{}.lock", std::process::id()));
+        let lock_file = try_lock_run_at(&path).unwrap();
+        assert!(lock_file.is_some());
+        assert!(try_lock_run_at(&path).unwrap().is_none());
+        drop(lock_file);
+        assert!(try_lock_run_at(&path).unwrap().is_some());
+        _ = fs::remove_file(&path);
+    }
+}
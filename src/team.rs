@@ -0,0 +1,178 @@
+use std::{
+    fs::{read_to_string, write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{GenResult, Shift, errors::ResultLog, ical};
+
+/*
+A team calendar combines the stored shifts of several webcom ical users into a single calendar
+It only reads what the other instances already saved in their kuma directory, nothing is loaded from webcom
+Configured with a JSON file, see scripts/team.json
+*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TeamConfig {
+    // The name shown in the calendar app
+    pub name: String,
+    // Where the team calendar is written to
+    pub output: PathBuf,
+    pub members: Vec<TeamMember>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TeamMember {
+    // The kuma directory of the webcom ical instance of this member, e.g. ../1234/kuma
+    pub directory: PathBuf,
+    #[serde(default)]
+    pub privacy: TeamPrivacy,
+}
+
+// How much of the shifts of a member the team can see
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TeamPrivacy {
+    // The shift number, kind, description and location
+    #[default]
+    Details,
+    // Only when the member is working
+    Busy,
+}
+
+// The shifts of a single member, ready to be put in the team calendar
+#[derive(Debug, Clone)]
+pub struct TeamShifts {
+    pub name: String,
+    pub privacy: TeamPrivacy,
+    pub shifts: Vec<Shift>,
+}
+
+impl TeamConfig {
+    pub fn load(path: &Path) -> GenResult<Self> {
+        Ok(serde_json::from_str(&read_to_string(path)?)?)
+    }
+}
+
+impl TeamMember {
+    /*
+    The name is the one the instance of this member found in webcom
+    If it has not found one yet, the name of the directory the kuma directory is in is used, which is the personeelsnummer
+    */
+    fn name(&self) -> String {
+        read_to_string(self.directory.join("name"))
+            .ok()
+            .map(|name| name.trim().to_owned())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| {
+                self.directory
+                    .canonicalize()
+                    .unwrap_or(self.directory.clone())
+                    .parent()
+                    .and_then(|parent| parent.file_name())
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            })
+    }
+
+    fn load_shifts(&self) -> GenResult<TeamShifts> {
+        Ok(TeamShifts {
+            name: self.name(),
+            privacy: self.privacy,
+            shifts: ical::load_stored_shifts_from(&self.directory)?,
+        })
+    }
+}
+
+/*
+Loads the stored shifts of every member and writes the team calendar
+Members whose shifts can not be loaded are left out, so one broken instance does not break the whole team calendar
+*/
+pub fn write_team_calendar(config_path: &Path) -> GenResult<()> {
+    let config = TeamConfig::load(config_path)?;
+    let members: Vec<TeamShifts> = config
+        .members
+        .iter()
+        .filter_map(|member| {
+            member
+                .load_shifts()
+                .warn_owned(&format!("Loading team shifts from {:?}", member.directory))
+                .ok()
+        })
+        .collect();
+    let calendar = ical::create_team_ical(&config.name, &members);
+    info!(
+        "Writing team calendar of {} members to: {:?}",
+        members.len(),
+        &config.output
+    );
+    write(&config.output, calendar.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::create_dir_all;
+    use time::{Date, Month};
+
+    fn save_member(directory: &Path, name: Option<&str>, number: &str) {
+        create_dir_all(directory).unwrap();
+        let date = Date::from_calendar_date(2025, Month::June, 2).unwrap();
+        let shift = Shift::test_shift(date, number);
+        write(
+            directory.join("relevant_events"),
+            serde_json::to_string(&vec![shift]).unwrap(),
+        )
+        .unwrap();
+        write(directory.join("non_relevant_events"), "[]").unwrap();
+        if let Some(name) = name {
+            write(directory.join("name"), name).unwrap();
+        }
+    }
+
+    #[test]
+    fn team_calendar_hides_details_of_busy_members() {
+        let base = std::env::temp_dir().join(format!("webcom_team_{}", std::process::id()));
+        save_member(&base.join("1234/kuma"), Some("Piet"), "V1");
+        save_member(&base.join("5678/kuma"), None, "V2");
+        let config = TeamConfig {
+            name: "Stalling".to_owned(),
+            output: base.join("team.ics"),
+            members: vec![
+                TeamMember {
+                    directory: base.join("1234/kuma"),
+                    privacy: TeamPrivacy::Details,
+                },
+                TeamMember {
+                    directory: base.join("5678/kuma"),
+                    privacy: TeamPrivacy::Busy,
+                },
+                TeamMember {
+                    directory: base.join("missing/kuma"),
+                    privacy: TeamPrivacy::Details,
+                },
+            ],
+        };
+        let config_path = base.join("team.json");
+        write(&config_path, serde_json::to_string(&config).unwrap()).unwrap();
+
+        write_team_calendar(&config_path).unwrap();
+        let calendar = read_to_string(&config.output).unwrap();
+        std::fs::remove_dir_all(&base).unwrap();
+
+        assert!(calendar.contains("Piet • Dienst - V1"));
+        assert!(calendar.contains("Lijn 401"));
+        assert!(calendar.contains("5678 • Bezet"));
+        assert!(!calendar.contains("V2"));
+    }
+
+    #[test]
+    fn privacy_defaults_to_details() {
+        let member: TeamMember = serde_json::from_str(r#"{"directory": "../1234/kuma"}"#).unwrap();
+        assert_eq!(member.privacy, TeamPrivacy::Details);
+        let member: TeamMember =
+            serde_json::from_str(r#"{"directory": "../1234/kuma", "privacy": "busy"}"#).unwrap();
+        assert_eq!(member.privacy, TeamPrivacy::Busy);
+    }
+}
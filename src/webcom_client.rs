@@ -0,0 +1,142 @@
+use std::{future::Future, time::Instant};
+
+use dotenvy::var;
+use time::{Date, Month};
+
+use crate::{
+    GenResult, browser_client::BrowserClient, http_client::HttpClient, messages::WebcomMessage,
+    metrics::record_page_load,
+};
+
+/*
+A single day in the roster of a month
+The tooltip contains the shift information, the day text starts with the day of the month
+*/
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct CalendarDay {
+    pub tooltip: Option<String>,
+    pub day_text: String,
+}
+
+// The text of every column of every row in the trip table of a shift page
+pub type ShiftRows = Vec<Vec<String>>;
+
+/*
+Everything webcom ical needs from the webcom site
+Implementations only fetch pages, all parsing of the shifts is done by the caller
+*/
+pub trait WebcomClient: Send {
+    // Opens webcom in a clean session. Goes to a fallback URL if the main URL does not work
+    fn open(&mut self, retry_count: usize) -> impl Future<Output = GenResult<()>> + Send;
+    // Signs in, returns a FailureType::SignInFailed if webcom shows why it failed
+    fn login(&mut self, user: &str, pass: &str) -> impl Future<Output = GenResult<()>> + Send;
+    // The first name of the signed in user. None if webcom redirected to the messages page
+    fn current_user_name(&mut self) -> impl Future<Output = GenResult<Option<String>>> + Send;
+    // The messages shown on the messages page, empty if webcom did not redirect there
+    fn pending_messages(&mut self) -> impl Future<Output = GenResult<Vec<WebcomMessage>>> + Send;
+    // Marks the messages as read if webcom asks for it, and leaves the messages page
+    fn acknowledge_messages(&mut self) -> impl Future<Output = GenResult<()>> + Send;
+    fn fetch_month(
+        &mut self,
+        year: i32,
+        month: Month,
+    ) -> impl Future<Output = GenResult<Vec<CalendarDay>>> + Send;
+    fn fetch_shift_detail(
+        &mut self,
+        date: Date,
+    ) -> impl Future<Output = GenResult<ShiftRows>> + Send;
+    fn quit(self) -> impl Future<Output = GenResult<()>> + Send;
+}
+
+// The backend is selected with WEBCOM_BACKEND, the browser is used unless it is set to http
+pub enum WebcomBackend {
+    Browser(BrowserClient),
+    Http(HttpClient),
+}
+
+impl WebcomBackend {
+    pub async fn connect() -> GenResult<Self> {
+        match var("WEBCOM_BACKEND").unwrap_or_default().as_str() {
+            "http" => {
+                debug!("Using HTTP backend");
+                Ok(Self::Http(HttpClient::new()?))
+            }
+            _ => {
+                debug!("Using browser backend");
+                Ok(Self::Browser(BrowserClient::new().await?))
+            }
+        }
+    }
+}
+
+impl WebcomClient for WebcomBackend {
+    async fn open(&mut self, retry_count: usize) -> GenResult<()> {
+        match self {
+            Self::Browser(client) => client.open(retry_count).await,
+            Self::Http(client) => client.open(retry_count).await,
+        }
+    }
+
+    async fn login(&mut self, user: &str, pass: &str) -> GenResult<()> {
+        let started = Instant::now();
+        let result = match self {
+            Self::Browser(client) => client.login(user, pass).await,
+            Self::Http(client) => client.login(user, pass).await,
+        };
+        record_page_load("login", started.elapsed());
+        result
+    }
+
+    async fn current_user_name(&mut self) -> GenResult<Option<String>> {
+        match self {
+            Self::Browser(client) => client.current_user_name().await,
+            Self::Http(client) => client.current_user_name().await,
+        }
+    }
+
+    async fn pending_messages(&mut self) -> GenResult<Vec<WebcomMessage>> {
+        match self {
+            Self::Browser(client) => client.pending_messages().await,
+            Self::Http(client) => client.pending_messages().await,
+        }
+    }
+
+    async fn acknowledge_messages(&mut self) -> GenResult<()> {
+        match self {
+            Self::Browser(client) => client.acknowledge_messages().await,
+            Self::Http(client) => client.acknowledge_messages().await,
+        }
+    }
+
+    async fn fetch_month(&mut self, year: i32, month: Month) -> GenResult<Vec<CalendarDay>> {
+        let started = Instant::now();
+        let result = match self {
+            Self::Browser(client) => client.fetch_month(year, month).await,
+            Self::Http(client) => client.fetch_month(year, month).await,
+        };
+        record_page_load("month", started.elapsed());
+        result
+    }
+
+    async fn fetch_shift_detail(&mut self, date: Date) -> GenResult<ShiftRows> {
+        let started = Instant::now();
+        let result = match self {
+            Self::Browser(client) => client.fetch_shift_detail(date).await,
+            Self::Http(client) => client.fetch_shift_detail(date).await,
+        };
+        record_page_load("shift_detail", started.elapsed());
+        result
+    }
+
+    async fn quit(self) -> GenResult<()> {
+        match self {
+            Self::Browser(client) => client.quit().await,
+            Self::Http(client) => client.quit().await,
+        }
+    }
+}
+
+// WEBCOM_URL can point to another webcom server, like a local test server. No fallback URLs are used then
+pub fn custom_webcom_url() -> Option<String> {
+    var("WEBCOM_URL").ok().filter(|url| !url.is_empty())
+}